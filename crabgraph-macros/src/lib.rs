@@ -0,0 +1,240 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Attribute, Data, DeriveInput, Expr, Fields, LitStr, Token, parse_macro_input};
+
+/// Derive `crabgraph::GraphState` for a struct with named fields.
+///
+/// Each field is a top-level key of the state. Declare the reducer of a key with
+/// `#[reducer(..)]`, any expression of a `crabgraph::state::Reducer`, e.g. `#[reducer(Append)]`.
+///
+/// The key of a field follows `#[serde(rename = ..)]` and `#[serde(rename_all = ..)]`, the way
+/// `Deserialize` reads it. Fields with `#[serde(skip)]` or `#[serde(skip_deserializing)]` are not
+/// part of the state, `#[serde(flatten)]` is not supported.
+///
+/// Also generates `<Name>Update`, the partial update of the state with a setter per field.
+#[proc_macro_derive(GraphState, attributes(reducer))]
+pub fn derive_graph_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "GraphState can't be derived for generic structs",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "GraphState needs a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "GraphState can only be derived for structs",
+            ));
+        }
+    };
+    let rename_all = serde_name(&input.attrs, "rename_all")?;
+    let ident = &input.ident;
+    let vis = &input.vis;
+    let update_ident = format_ident!("{}Update", ident);
+    let update_doc =
+        format!("A partial update of [`{ident}`], only the fields that are set are written.");
+    let mut update_fields = Vec::new();
+    let mut setters = Vec::new();
+    let mut writes = Vec::new();
+    let mut reducers = Vec::new();
+    for field in fields {
+        let field_ident = field.ident.as_ref().expect("named field");
+        let field_vis = &field.vis;
+        let ty = &field.ty;
+        if let Some(flatten) = serde_flag(&field.attrs, "flatten")? {
+            return Err(syn::Error::new_spanned(
+                flatten,
+                "GraphState doesn't support flattened fields",
+            ));
+        }
+        let reducer_attrs: Vec<_> = field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("reducer"))
+            .collect();
+        if serde_flag(&field.attrs, "skip")?.is_some()
+            || serde_flag(&field.attrs, "skip_deserializing")?.is_some()
+        {
+            // the state is never read into the field, there is nothing to update
+            if let Some(attr) = reducer_attrs.first() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "a skipped field can't have a reducer",
+                ));
+            }
+            continue;
+        }
+        let name = field_ident.to_string().trim_start_matches("r#").to_string();
+        let key = match (serde_name(&field.attrs, "rename")?, &rename_all) {
+            (Some(rename), _) => rename.value(),
+            (None, Some(rule)) => rename_field(rule, &name)?,
+            (None, None) => name,
+        };
+        update_fields.push(quote! {
+            #field_vis #field_ident: ::core::option::Option<#ty>
+        });
+        setters.push(quote! {
+            #field_vis fn #field_ident(mut self, #field_ident: #ty) -> Self {
+                self.#field_ident = ::core::option::Option::Some(#field_ident);
+                self
+            }
+        });
+        writes.push(quote! {
+            if let ::core::option::Option::Some(value) = self.#field_ident {
+                match ::crabgraph::__private::serde_json::to_value(value) {
+                    ::core::result::Result::Ok(value) => {
+                        object.insert(#key.to_string(), value);
+                    }
+                    ::core::result::Result::Err(error) => {
+                        ::crabgraph::__private::tracing::warn!(
+                            key = #key,
+                            %error,
+                            "Skipping a field that doesn't serialize to JSON"
+                        );
+                    }
+                }
+            }
+        });
+        for attr in reducer_attrs {
+            let reducer: Expr = attr.parse_args()?;
+            reducers.push(quote! {
+                reducers.insert(#key.to_string(), ::std::sync::Arc::new(#reducer));
+            });
+        }
+    }
+    Ok(quote! {
+        #[doc = #update_doc]
+        #[derive(Default)]
+        #vis struct #update_ident {
+            #(#update_fields,)*
+        }
+
+        impl #update_ident {
+            #(#setters)*
+        }
+
+        impl ::crabgraph::__private::Modification<::crabgraph::JsonObject> for #update_ident {
+            #[allow(unused_variables)]
+            fn modify(self, object: &mut ::crabgraph::JsonObject) {
+                #(#writes)*
+            }
+        }
+
+        impl ::crabgraph::GraphState for #ident {
+            type Update = #update_ident;
+            fn reducers() -> ::crabgraph::state::Reducers {
+                #[allow(unused_mut)]
+                let mut reducers = ::crabgraph::state::Reducers::default();
+                #(#reducers)*
+                reducers
+            }
+        }
+    })
+}
+
+/// The value of `#[serde(<item> = "..")]`, or of the `deserialize` half of
+/// `#[serde(<item>(serialize = "..", deserialize = ".."))]` as that is the name the state is read
+/// with.
+fn serde_name(attrs: &[Attribute], item: &str) -> syn::Result<Option<LitStr>> {
+    let mut name = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(item) {
+                if meta.input.peek(Token![=]) {
+                    name = Some(meta.value()?.parse()?);
+                } else {
+                    meta.parse_nested_meta(|meta| {
+                        let value: LitStr = meta.value()?.parse()?;
+                        if meta.path.is_ident("deserialize") {
+                            name = Some(value);
+                        }
+                        Ok(())
+                    })?;
+                }
+            } else if meta.input.peek(Token![=]) {
+                meta.value()?.parse::<Expr>()?;
+            } else if !meta.input.is_empty() && !meta.input.peek(Token![,]) {
+                meta.input.parse::<proc_macro2::TokenTree>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(name)
+}
+
+/// The path of the flag `#[serde(<item>)]`, if it is set.
+fn serde_flag(attrs: &[Attribute], item: &str) -> syn::Result<Option<syn::Path>> {
+    let mut flag = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(item) {
+                flag = Some(meta.path.clone());
+            }
+            if meta.input.peek(Token![=]) {
+                meta.value()?.parse::<Expr>()?;
+            } else if !meta.input.is_empty() && !meta.input.peek(Token![,]) {
+                meta.input.parse::<proc_macro2::TokenTree>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(flag)
+}
+
+/// The name of a snake case field under a serde `rename_all` rule.
+fn rename_field(rule: &LitStr, field: &str) -> syn::Result<String> {
+    let pascal = || {
+        let mut pascal = String::new();
+        let mut capitalize = true;
+        for ch in field.chars() {
+            if ch == '_' {
+                capitalize = true;
+            } else if capitalize {
+                pascal.push(ch.to_ascii_uppercase());
+                capitalize = false;
+            } else {
+                pascal.push(ch);
+            }
+        }
+        pascal
+    };
+    Ok(match rule.value().as_str() {
+        "lowercase" | "snake_case" => field.to_string(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => field.to_ascii_uppercase(),
+        "PascalCase" => pascal(),
+        "camelCase" => {
+            let pascal = pascal();
+            let mut chars = pascal.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        }
+        "kebab-case" => field.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => field.to_ascii_uppercase().replace('_', "-"),
+        _ => {
+            return Err(syn::Error::new_spanned(
+                rule,
+                "unknown rename_all rule for GraphState",
+            ));
+        }
+    })
+}
@@ -1,6 +1,14 @@
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 
-use crabgraph::{Graph, NodeError, map, node::NodeKey, state::State, typed::json::TypedState};
+use crabgraph::{
+    Graph, NodeError,
+    edge::Goto,
+    extension::Extension,
+    node::{Command, NodeKey, NodeOptions},
+    retry::RetryPolicy,
+    state::State,
+    typed::json::Json,
+};
 use genai::chat::{ChatMessage, ChatOptions, ChatRequest, JsonSpec, Tool};
 
 use serde::{Deserialize, Serialize};
@@ -9,7 +17,7 @@ use crate::{
     App, Config,
     prompts::{AnswerInstructions, Prompt, QueryWriter, Reflection, WebSearch},
     state::{
-        AddLoopCount, OverallState, OverallStateUpdate, QueryGenerationState, ReflectionState,
+        OverallState, OverallStateUpdate, QueryGenerationState, ReflectionState,
         ReflectionStateUpdate, WebSearchState,
     },
     utils,
@@ -28,11 +36,10 @@ pub struct SearchQueryList {
 }
 
 async fn generate_query(
-    state: State,
-    config: Arc<Config>,
-    llm: genai::Client,
-) -> Result<(), NodeError> {
-    let overall_state = state.fetch_view(TypedState::<OverallState>::new()).await?;
+    Json(overall_state): Json<OverallState>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(llm): Extension<genai::Client>,
+) -> Result<QueryGenerationState, NodeError> {
     let number_queries = overall_state.initial_search_query_count;
     let chat_option = ChatOptions::default()
         .with_response_format(JsonSpec::new(
@@ -56,18 +63,15 @@ async fn generate_query(
         .into_first_text()
         .unwrap_or_default();
     let response = serde_json::from_str::<QueryGenerationState>(&response)?;
-    state.apply_modification(response).await;
-    Ok(())
+    Ok(response)
 }
 
 async fn web_research(
     state: State,
-    llm: genai::Client,
-    config: Arc<Config>,
+    Json(query_state): Json<QueryGenerationState>,
+    Extension(llm): Extension<genai::Client>,
+    Extension(config): Extension<Arc<Config>>,
 ) -> Result<(), NodeError> {
-    let query_state = state
-        .fetch_view(TypedState::<QueryGenerationState>::new())
-        .await?;
     let total_query_count = query_state.search_query.len();
     for (idx, query) in query_state.search_query.into_iter().enumerate() {
         tracing::info!(
@@ -112,11 +116,11 @@ async fn web_research(
             .collect::<Vec<_>>();
 
         state
-            .apply_modification(OverallStateUpdate {
-                sources_gathered,
-                web_research_result: vec![serde_json::json!(modified_text)],
-                ..Default::default()
-            })
+            .apply_modification(
+                OverallStateUpdate::default()
+                    .sources_gathered(sources_gathered)
+                    .web_research_result(vec![serde_json::json!(modified_text)]),
+            )
             .await;
         tracing::info!("Web search response for query ({idx}/{total_query_count})",);
     }
@@ -124,11 +128,10 @@ async fn web_research(
 }
 
 async fn reflection(
-    state: State,
-    config: Arc<Config>,
-    llm: genai::Client,
-) -> Result<(), NodeError> {
-    let overall_state = state.fetch_view(TypedState::<OverallState>::new()).await?;
+    Json(overall_state): Json<OverallState>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(llm): Extension<genai::Client>,
+) -> Result<Command, NodeError> {
     tracing::info!("Starting reflection process...");
 
     let chat_option = ChatOptions::default()
@@ -161,45 +164,36 @@ async fn reflection(
 
     let reflection_result = serde_json::from_str::<ReflectionState>(&response)?;
 
-    state
-        .apply_modification((
-            AddLoopCount,
-            ReflectionStateUpdate {
-                is_sufficient: reflection_result.is_sufficient,
-                knowledge_gap: reflection_result.knowledge_gap,
-                follow_up_queries: reflection_result.follow_up_queries,
-                number_of_ran_queries: overall_state.search_query.len(),
-                ..Default::default()
-            },
-        ))
-        .await;
-
-    Ok(())
-}
-
-async fn evaluate_research(state: State, config: Arc<Config>) -> Result<NodeKey, NodeError> {
-    let overall_state = state.fetch_view(TypedState::<OverallState>::new()).await?;
+    let research_loop_count = overall_state.research_loop_count + 1;
     let max_research_loops = config.max_research_loops;
     tracing::info!(
         "Evaluating research: is_sufficient: {}, research_loop_count: {}, max_research_loops: {}",
-        overall_state.is_sufficient,
-        overall_state.research_loop_count,
+        reflection_result.is_sufficient,
+        research_loop_count,
         max_research_loops
     );
-    if overall_state.is_sufficient || overall_state.research_loop_count >= max_research_loops {
-        Ok(FINALIZE_ANSWER)
+    let next = if reflection_result.is_sufficient || research_loop_count >= max_research_loops {
+        FINALIZE_ANSWER
     } else {
         // TODO: Implement parallel web research for follow-up queries
-        Ok(WEB_SEARCH)
-    }
+        WEB_SEARCH
+    };
+
+    Ok(Command::goto(next).with_update(ReflectionStateUpdate {
+        is_sufficient: reflection_result.is_sufficient,
+        knowledge_gap: reflection_result.knowledge_gap,
+        follow_up_queries: reflection_result.follow_up_queries,
+        number_of_ran_queries: overall_state.search_query.len(),
+        research_loop_count: 1,
+        ..Default::default()
+    }))
 }
 
 async fn finalize_answer(
-    state: State,
-    config: Arc<Config>,
-    llm: genai::Client,
-) -> Result<(), NodeError> {
-    let overall_state = state.fetch_view(TypedState::<OverallState>::new()).await?;
+    Json(overall_state): Json<OverallState>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(llm): Extension<genai::Client>,
+) -> Result<OverallStateUpdate, NodeError> {
     tracing::info!("Finalizing answer...");
     let chat_option = ChatOptions::default();
 
@@ -238,39 +232,28 @@ async fn finalize_answer(
         }
     }
 
-    state
-        .apply_modification(OverallStateUpdate {
-            messages: vec![crate::model::Message::ai(response)],
-            sources_gathered: unique_source,
-            ..Default::default()
-        })
-        .await;
-
-    Ok(())
+    Ok(OverallStateUpdate::default()
+        .messages(vec![crate::model::Message::ai(response)])
+        .sources_gathered(unique_source))
 }
 
 pub async fn graph() -> Result<Arc<Graph<App>>, crabgraph::Error> {
     let mut graph = Graph::<App>::new();
+    // the llm sometimes answers with malformed json, ask again
+    let retry_malformed_json = NodeOptions::new()
+        .with_retry(RetryPolicy::new(3).retry_on(|error| error.is::<serde_json::Error>()));
     graph
-        .add_node(GENERATE_QUERY, generate_query)
+        .set_state::<OverallState>()
+        .add_node_with(GENERATE_QUERY, generate_query, retry_malformed_json.clone())
         .add_node(WEB_SEARCH, web_research)
-        .add_node(REFLECTION, reflection)
+        .add_node_with(REFLECTION, reflection, retry_malformed_json)
         .add_node(FINALIZE_ANSWER, finalize_answer);
 
     graph
         .add_edge(NodeKey::Start, GENERATE_QUERY)
         .add_edge(GENERATE_QUERY, WEB_SEARCH)
         .add_edge(WEB_SEARCH, REFLECTION)
-        .add_edge(
-            REFLECTION,
-            (
-                evaluate_research,
-                map! {
-                    FINALIZE_ANSWER => FINALIZE_ANSWER,
-                    WEB_SEARCH => WEB_SEARCH
-                },
-            ),
-        )
+        .add_edge(REFLECTION, Goto::new([FINALIZE_ANSWER, WEB_SEARCH]))
         .add_edge(FINALIZE_ANSWER, NodeKey::End);
 
     let graph = graph.compile()?;
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crabgraph::{Context, request::FromRequest, state::State, typed::json::JsonValueView};
+use crabgraph::{Context, state::State};
 use genai::{ModelIden, adapter::AdapterKind, resolver::AuthData};
 
 use serde::{Deserialize, Serialize};
@@ -13,11 +13,9 @@ mod prompts;
 mod state;
 mod utils;
 // Graph
-#[derive(Debug, Clone)]
-pub struct App {
-    config: Arc<Config>,
-    llm: genai::Client,
-}
+// the nodes take their dependencies from the extensions of the context
+#[derive(Debug, Clone, Default)]
+pub struct App;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -28,20 +26,6 @@ pub struct Config {
     max_research_loops: u32,
 }
 
-impl FromRequest<App> for Arc<Config> {
-    fn from_request(request: &crabgraph::request::Request<App>) -> Result<Self, crabgraph::Error> {
-        let config = request.context.state.config.clone();
-        Ok(config)
-    }
-}
-
-impl FromRequest<App> for genai::Client {
-    fn from_request(request: &crabgraph::request::Request<App>) -> Result<Self, crabgraph::Error> {
-        let llm = request.context.state.llm.clone();
-        Ok(llm)
-    }
-}
-
 #[tokio::main]
 pub async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
@@ -55,20 +39,19 @@ pub async fn main() -> anyhow::Result<()> {
         number_of_initial_queries: 3,
         max_research_loops: 1,
     });
-    let app = App {
-        config: config.clone(),
-        llm: genai::Client::builder()
-            .with_auth_resolver_fn(|iden: ModelIden| {
-                if matches!(iden.adapter_kind, AdapterKind::Gemini) {
-                    Ok(Some(AuthData::from_env("GEMINI_API_KEY")))
-                } else {
-                    Ok(None)
-                }
-            })
-            .build(),
-    };
+    let llm = genai::Client::builder()
+        .with_auth_resolver_fn(|iden: ModelIden| {
+            if matches!(iden.adapter_kind, AdapterKind::Gemini) {
+                Ok(Some(AuthData::from_env("GEMINI_API_KEY")))
+            } else {
+                Ok(None)
+            }
+        })
+        .build();
 
-    let context = Context { state: app };
+    let context = Context::new(App)
+        .with_extension(config.clone())
+        .with_extension(llm);
     let graph = graph().await?;
     let request = context.new_request(State::from_typed(OverallState {
         messages: vec![Message::human("请问中国境内目前有哪些生产ddr4内存的厂商？")],
@@ -78,9 +61,8 @@ pub async fn main() -> anyhow::Result<()> {
         reasoning_model: config.reflection_model.clone(),
         ..Default::default()
     })?);
-    graph.run(request.clone()).await?;
-    let result = request.state.fetch_view(JsonValueView).await;
-    let value_to_string_pretty = serde_json::to_string_pretty(&result)?;
+    let response = graph.run(request).await?;
+    let value_to_string_pretty = serde_json::to_string_pretty(&response.state)?;
     tracing::info!("Graph execution completed {value_to_string_pretty}");
     Ok(())
 }
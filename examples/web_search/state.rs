@@ -1,6 +1,7 @@
-use std::ops::Add;
-
-use crabgraph::{JsonObject, JsonValue};
+use crabgraph::{
+    GraphState, JsonValue,
+    state::{Append, Sum},
+};
 use modify::Modification;
 use modify_json::{
     ensure::{array_field, boolean_field, field, number_field, string_field},
@@ -10,23 +11,28 @@ use serde::{Deserialize, Serialize};
 
 use crate::model::Message;
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, schemars::JsonSchema, GraphState)]
 pub struct OverallState {
     #[serde(default)]
+    #[reducer(Append)]
     pub messages: Vec<Message>,
     #[serde(default)]
     pub initial_search_query_count: u32,
     #[serde(default)]
     pub max_research_loops: u32,
     #[serde(default)]
+    #[reducer(Sum)]
     pub research_loop_count: u32,
     #[serde(default)]
     pub reasoning_model: String,
     #[serde(default)]
+    #[reducer(Append)]
     pub sources_gathered: Vec<JsonValue>,
     #[serde(default)]
+    #[reducer(Append)]
     pub web_research_result: Vec<JsonValue>,
     #[serde(default)]
+    #[reducer(Append)]
     pub search_query: Vec<JsonValue>,
     #[serde(default)]
     pub is_sufficient: bool,
@@ -40,43 +46,6 @@ pub struct OverallState {
     pub final_answer: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, Modification)]
-#[modify(target = "crabgraph::JsonObject")]
-pub struct OverallStateUpdate {
-    #[serde(default)]
-    #[modify(by = array_field("messages").then(ExtendJsonArray))]
-    pub messages: Vec<Message>,
-    #[serde(default)]
-    #[modify(by = string_field("reasoning_model").then(Set))]
-    pub reasoning_model: String,
-    #[serde(default)]
-    #[modify(by = array_field("sources_gathered").then(Extend))]
-    pub sources_gathered: Vec<JsonValue>,
-    #[serde(default)]
-    #[modify(by = array_field("web_research_result").then(Extend))]
-    pub web_research_result: Vec<JsonValue>,
-    #[serde(default)]
-    #[modify(by = array_field("search_query").then(Extend))]
-    pub search_query: Vec<JsonValue>,
-}
-
-pub struct AddLoopCount;
-
-impl Modification<JsonObject> for AddLoopCount {
-    fn modify(self, value: &mut JsonObject) {
-        value
-            .entry("research_loop_count")
-            .and_modify(|v| {
-                if let JsonValue::Number(num) = v {
-                    if let Some(current) = num.as_u64() {
-                        *num = serde_json::Number::from(current.add(1));
-                    }
-                }
-            })
-            .or_insert(JsonValue::Number(1.into()));
-    }
-}
-
 impl OverallState {
     pub fn get_research_topic(&self) -> String {
         Message::get_research_topic(&self.messages)
@@ -93,7 +62,7 @@ pub struct Query {
 #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema, Modification)]
 #[modify(target = "crabgraph::JsonObject")]
 pub struct QueryGenerationState {
-    #[modify(by = array_field("search_query").then(ExtendJsonArray))]
+    #[modify(by = field("search_query").then(SetJson))]
     pub search_query: Vec<Query>,
 }
 
@@ -128,6 +97,10 @@ pub struct ReflectionStateUpdate {
     #[serde(default)]
     #[modify(by = field("number_of_ran_queries").then(SetJson))]
     pub number_of_ran_queries: usize,
+    /// Added to the loop count.
+    #[serde(default)]
+    #[modify(by = field("research_loop_count").then(SetJson))]
+    pub research_loop_count: u32,
     #[serde(default)]
     #[modify(by = string_field("final_answer").then(Set))]
     pub final_answer: String,
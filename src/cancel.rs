@@ -0,0 +1,50 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use tokio::sync::Notify;
+
+use crate::request::{FromRequest, Request};
+
+/// Cooperative cancellation of a run.
+///
+/// Cancelling stops the graph from scheduling new nodes, nodes that are already running can
+/// observe the token through the [`FromRequest`] extractor and stop early.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<CancellationInner>);
+
+#[derive(Debug, Default)]
+struct CancellationInner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        if !self.0.cancelled.swap(true, Ordering::SeqCst) {
+            self.0.notify.notify_waiters();
+        }
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+    /// Resolves once the token is cancelled.
+    pub async fn cancelled(&self) {
+        // a `Notified` receives `notify_waiters` as soon as it is created
+        let notified = self.0.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+impl<S> FromRequest<S> for CancellationToken {
+    fn from_request(request: &Request<S>) -> Result<Self, crate::Error> {
+        Ok(request.config.cancellation.clone())
+    }
+}
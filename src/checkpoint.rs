@@ -0,0 +1,304 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::future::BoxFuture;
+use modify::Modification;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error, Graph, JsonObject, Response, edge::Dispatch, interrupt::InterruptKind, node::NodeKey,
+    request::Request, run::RunRecorder, state::State,
+};
+
+mod file;
+pub use file::FileCheckpointer;
+mod memory;
+pub use memory::InMemoryCheckpointer;
+
+/// Stores the [`Checkpoint`]s of runs, grouped by thread.
+pub trait Checkpointer: Send + Sync + 'static {
+    fn put(&self, checkpoint: Checkpoint) -> BoxFuture<'_, Result<(), Error>>;
+    /// The checkpoint with `checkpoint_id`, or the latest one of the thread if `None`.
+    fn get<'a>(
+        &'a self,
+        thread_id: &'a str,
+        checkpoint_id: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<Checkpoint>, Error>>;
+    /// Every checkpoint of the thread, oldest first.
+    fn list<'a>(&'a self, thread_id: &'a str) -> BoxFuture<'a, Result<Vec<Checkpoint>, Error>>;
+}
+
+/// The state of a run between two nodes (or supersteps), enough to continue the run from there.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub thread_id: String,
+    pub checkpoint_id: String,
+    /// The checkpoint this one continues from.
+    pub parent_id: Option<String>,
+    pub state: JsonObject,
+    /// The nodes that are still to run, empty once the run is complete.
+    ///
+    /// This includes the nodes waiting at a join, and in async mode the nodes that were running
+    /// when the checkpoint was saved. Failures routed to an error handler are not kept.
+    pub frontier: Vec<NodeKey>,
+    /// Invocations still to run that have an input of their own.
+    #[serde(default)]
+    pub dispatches: Vec<Dispatch>,
+    /// The invocation the run stopped at, the only one that gets the
+    /// [`RunConfig::resume`](crate::request::RunConfig::resume) value. `None` after an interrupt
+    /// after a node.
+    #[serde(default)]
+    pub interrupted: Option<Dispatch>,
+    /// The subgraphs that paused with the run, each continues from its checkpoint on resume.
+    #[serde(default)]
+    pub subgraphs: Vec<PausedSubgraph>,
+    pub metadata: CheckpointMetadata,
+}
+
+/// Where the subgraph run by an interrupted invocation paused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PausedSubgraph {
+    pub invocation: Dispatch,
+    pub checkpoint: Checkpoint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointMetadata {
+    /// Number of completed supersteps, or node executions in async mode.
+    pub step: usize,
+    pub source: CheckpointSource,
+    /// Milliseconds since the unix epoch.
+    pub created_at: u64,
+}
+
+/// What produced a checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckpointSource {
+    /// The input of the run, before any node ran.
+    Input,
+    /// A node (or superstep) completed.
+    Loop,
+    /// The run stopped at an interrupt.
+    Interrupt(InterruptKind),
+    /// A fork with an edited state, see [`Graph::update_state`].
+    Update,
+}
+
+impl<S> Graph<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn require_checkpointer(&self) -> Result<&dyn Checkpointer, Error> {
+        self.checkpointer
+            .as_deref()
+            .ok_or_else(|| Error::CheckpointError("the graph has no checkpointer".into()))
+    }
+    /// The checkpointer of the graph and the thread of `request`.
+    pub(crate) fn checkpoint_thread<'a>(
+        &'a self,
+        request: &'a Request<S>,
+    ) -> Result<(&'a dyn Checkpointer, &'a str), Error> {
+        let checkpointer = self.require_checkpointer()?;
+        let thread_id = request
+            .config
+            .thread_id
+            .as_deref()
+            .ok_or_else(|| Error::CheckpointError("the request has no thread id".into()))?;
+        Ok((checkpointer, thread_id))
+    }
+    /// Continue the thread of `request` from its latest checkpoint, after a crash for example.
+    ///
+    /// The state of `request` is replaced by the state of the checkpoint.
+    pub async fn resume(self: Arc<Self>, request: Request<S>) -> Result<Response, Error> {
+        let (checkpointer, thread_id) = self.checkpoint_thread(&request)?;
+        let checkpoint = checkpointer.get(thread_id, None).await?.ok_or_else(|| {
+            Error::CheckpointError(format!("no checkpoint for thread {thread_id}").into())
+        })?;
+        self.run_from_checkpoint(request, checkpoint).await
+    }
+    /// Run the invocations pending at `checkpoint` on its state, new checkpoints are
+    /// children of it.
+    ///
+    /// Continuing from an interrupt before a node runs that node, set
+    /// [`RunConfig::resume`](crate::request::RunConfig::resume) to hand a value to the nodes.
+    pub async fn run_from_checkpoint(
+        self: Arc<Self>,
+        request: Request<S>,
+        checkpoint: Checkpoint,
+    ) -> Result<Response, Error> {
+        self.execute(request, RunRecorder::new(), Some(checkpoint))
+            .await
+    }
+    /// Every checkpoint of the thread, oldest first.
+    pub async fn state_history(&self, thread_id: &str) -> Result<Vec<Checkpoint>, Error> {
+        self.require_checkpointer()?.list(thread_id).await
+    }
+    /// The checkpoint with `checkpoint_id`, or the latest one of the thread. Its `state` is the
+    /// state at that point and its `frontier` the nodes that would run next.
+    pub async fn get_state(
+        &self,
+        thread_id: &str,
+        checkpoint_id: Option<&str>,
+    ) -> Result<Option<Checkpoint>, Error> {
+        self.require_checkpointer()?
+            .get(thread_id, checkpoint_id)
+            .await
+    }
+    /// Fork the thread at `checkpoint_id` with `modification` applied to its state, through the
+    /// reducers of the graph like a node write.
+    ///
+    /// The fork is saved as the latest checkpoint of the thread, a child of `checkpoint_id` with
+    /// the same frontier. Continue from it with [`Graph::run_from_checkpoint`] or
+    /// [`Graph::resume`], the checkpoints before it stay untouched.
+    pub async fn update_state<M>(
+        &self,
+        thread_id: &str,
+        checkpoint_id: &str,
+        modification: M,
+    ) -> Result<Checkpoint, Error>
+    where
+        M: Modification<JsonObject>,
+    {
+        let checkpointer = self.require_checkpointer()?;
+        let parent = checkpointer
+            .get(thread_id, Some(checkpoint_id))
+            .await?
+            .ok_or_else(|| {
+                Error::CheckpointError(
+                    format!("no checkpoint {checkpoint_id} in thread {thread_id}").into(),
+                )
+            })?;
+        let mut state = parent.state.clone();
+        for write in self.reducers.writes(&state, modification) {
+            self.reducers.apply(&mut state, write);
+        }
+        let checkpoint = Checkpoint {
+            thread_id: parent.thread_id,
+            checkpoint_id: new_checkpoint_id(),
+            parent_id: Some(parent.checkpoint_id),
+            state,
+            frontier: parent.frontier,
+            dispatches: parent.dispatches,
+            interrupted: parent.interrupted,
+            subgraphs: parent.subgraphs,
+            metadata: CheckpointMetadata {
+                step: parent.metadata.step,
+                source: CheckpointSource::Update,
+                created_at: now_millis(),
+            },
+        };
+        checkpointer.put(checkpoint.clone()).await?;
+        Ok(checkpoint)
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// A unique id that sorts by creation time.
+fn new_checkpoint_id() -> String {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "{nanos:032x}-{:08x}",
+        SEQUENCE.fetch_add(1, Ordering::Relaxed) as u32
+    )
+}
+
+/// Builds the checkpoints of one run, each one the child of the previous, and saves them if the
+/// run has a checkpointer and a thread.
+pub(crate) struct CheckpointWriter {
+    checkpointer: Option<Arc<dyn Checkpointer>>,
+    thread_id: String,
+    parent_id: Option<String>,
+    step: usize,
+}
+
+impl CheckpointWriter {
+    pub(crate) fn new<S>(
+        graph: &Graph<S>,
+        request: &Request<S>,
+        parent: Option<&Checkpoint>,
+    ) -> Self {
+        CheckpointWriter {
+            checkpointer: graph
+                .checkpointer
+                .clone()
+                .filter(|_| request.config.thread_id.is_some()),
+            thread_id: request.config.thread_id.clone().unwrap_or_default(),
+            parent_id: parent.map(|checkpoint| checkpoint.checkpoint_id.clone()),
+            step: parent.map_or(0, |checkpoint| checkpoint.metadata.step),
+        }
+    }
+    /// Save the current state, with `pending` as the invocations still to run.
+    pub(crate) async fn save(
+        &mut self,
+        state: &State,
+        pending: Vec<Dispatch>,
+        source: CheckpointSource,
+    ) -> Result<(), Error> {
+        if self.checkpointer.is_some() {
+            self.checkpoint(state, pending, source, None, Vec::new())
+                .await?;
+        } else if source == CheckpointSource::Loop {
+            self.step += 1;
+        }
+        Ok(())
+    }
+    /// Like [`CheckpointWriter::save`], but also builds the checkpoint if there is nowhere to save
+    /// it. `interrupted` is the invocation the run stopped at, `subgraphs` the subgraphs that
+    /// paused with it.
+    pub(crate) async fn checkpoint(
+        &mut self,
+        state: &State,
+        pending: Vec<Dispatch>,
+        source: CheckpointSource,
+        interrupted: Option<Dispatch>,
+        subgraphs: Vec<PausedSubgraph>,
+    ) -> Result<Checkpoint, Error> {
+        if source == CheckpointSource::Loop {
+            self.step += 1;
+        }
+        let (dispatches, frontier): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|dispatch| dispatch.input.is_some());
+        let checkpoint = Checkpoint {
+            thread_id: self.thread_id.clone(),
+            checkpoint_id: new_checkpoint_id(),
+            parent_id: self.parent_id.clone(),
+            state: state.snapshot().await,
+            frontier: frontier.into_iter().map(|dispatch| dispatch.node).collect(),
+            dispatches,
+            interrupted,
+            subgraphs,
+            metadata: CheckpointMetadata {
+                step: self.step,
+                source,
+                created_at: now_millis(),
+            },
+        };
+        self.parent_id = Some(checkpoint.checkpoint_id.clone());
+        if let Some(checkpointer) = &self.checkpointer {
+            tracing::debug!(
+                thread_id = %checkpoint.thread_id,
+                checkpoint_id = %checkpoint.checkpoint_id,
+                "Saving checkpoint"
+            );
+            checkpointer.put(checkpoint.clone()).await?;
+        }
+        Ok(checkpoint)
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use futures::future::BoxFuture;
+use tokio::io::AsyncWriteExt;
+
+use super::{Checkpoint, Checkpointer};
+use crate::Error;
+
+/// Appends checkpoints to one JSON lines file per thread, `<directory>/<thread_id>.jsonl`.
+///
+/// A line cut short by a crash is skipped when reading.
+#[derive(Debug, Clone)]
+pub struct FileCheckpointer {
+    directory: PathBuf,
+}
+
+impl FileCheckpointer {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        FileCheckpointer {
+            directory: directory.into(),
+        }
+    }
+    fn thread_path(&self, thread_id: &str) -> Result<PathBuf, Error> {
+        let valid = !thread_id.is_empty()
+            && thread_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Error::CheckpointError(
+                format!("thread id {thread_id:?} is not a valid file name").into(),
+            ));
+        }
+        Ok(self.directory.join(format!("{thread_id}.jsonl")))
+    }
+    async fn read_thread(&self, thread_id: &str) -> Result<Vec<Checkpoint>, Error> {
+        let content = match tokio::fs::read_to_string(self.thread_path(thread_id)?).await {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(Error::CheckpointError(error.into())),
+        };
+        let checkpoints = content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(checkpoint) => Some(checkpoint),
+                Err(error) => {
+                    tracing::warn!(thread_id, %error, "Skipping unreadable checkpoint");
+                    None
+                }
+            })
+            .collect();
+        Ok(checkpoints)
+    }
+}
+
+impl Checkpointer for FileCheckpointer {
+    fn put(&self, checkpoint: Checkpoint) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(async move {
+            let path = self.thread_path(&checkpoint.thread_id)?;
+            let mut line = serde_json::to_vec(&checkpoint)?;
+            line.push(b'\n');
+            tokio::fs::create_dir_all(&self.directory)
+                .await
+                .map_err(|error| Error::CheckpointError(error.into()))?;
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await
+                .map_err(|error| Error::CheckpointError(error.into()))?;
+            file.write_all(&line)
+                .await
+                .map_err(|error| Error::CheckpointError(error.into()))?;
+            file.sync_data()
+                .await
+                .map_err(|error| Error::CheckpointError(error.into()))
+        })
+    }
+    fn get<'a>(
+        &'a self,
+        thread_id: &'a str,
+        checkpoint_id: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<Checkpoint>, Error>> {
+        Box::pin(async move {
+            let mut checkpoints = self.read_thread(thread_id).await?;
+            Ok(match checkpoint_id {
+                Some(checkpoint_id) => checkpoints
+                    .into_iter()
+                    .find(|checkpoint| checkpoint.checkpoint_id == checkpoint_id),
+                None => checkpoints.pop(),
+            })
+        })
+    }
+    fn list<'a>(&'a self, thread_id: &'a str) -> BoxFuture<'a, Result<Vec<Checkpoint>, Error>> {
+        Box::pin(self.read_thread(thread_id))
+    }
+}
@@ -0,0 +1,53 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use futures::future::{BoxFuture, ready};
+
+use super::{Checkpoint, Checkpointer};
+use crate::Error;
+
+/// Keeps checkpoints in memory, they are gone with the process.
+#[derive(Debug, Default)]
+pub struct InMemoryCheckpointer {
+    threads: Mutex<HashMap<String, Vec<Checkpoint>>>,
+}
+
+impl InMemoryCheckpointer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Checkpointer for InMemoryCheckpointer {
+    fn put(&self, checkpoint: Checkpoint) -> BoxFuture<'_, Result<(), Error>> {
+        self.threads
+            .lock()
+            .expect("checkpoints poisoned")
+            .entry(checkpoint.thread_id.clone())
+            .or_default()
+            .push(checkpoint);
+        Box::pin(ready(Ok(())))
+    }
+    fn get<'a>(
+        &'a self,
+        thread_id: &'a str,
+        checkpoint_id: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<Option<Checkpoint>, Error>> {
+        let threads = self.threads.lock().expect("checkpoints poisoned");
+        let checkpoints = threads
+            .get(thread_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let checkpoint = match checkpoint_id {
+            Some(checkpoint_id) => checkpoints
+                .iter()
+                .find(|checkpoint| checkpoint.checkpoint_id == checkpoint_id),
+            None => checkpoints.last(),
+        };
+        Box::pin(ready(Ok(checkpoint.cloned())))
+    }
+    fn list<'a>(&'a self, thread_id: &'a str) -> BoxFuture<'a, Result<Vec<Checkpoint>, Error>> {
+        let threads = self.threads.lock().expect("checkpoints poisoned");
+        let checkpoints = threads.get(thread_id).cloned().unwrap_or_default();
+        Box::pin(ready(Ok(checkpoints)))
+    }
+}
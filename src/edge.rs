@@ -4,10 +4,20 @@ use crate::{Request, node::NodeKey, utils::IntoSet};
 
 mod function;
 pub use function::EdgeFunction;
+mod fan_out;
+pub use fan_out::{Dispatch, FanOut, IntoDispatches};
+mod goto;
 use futures::future::{BoxFuture, ready};
+pub use goto::Goto;
 pub trait Edge<S>: Send + Sync + 'static {
     fn next_nodes(&self, request: &Request<S>)
     -> BoxFuture<Result<HashSet<NodeKey>, crate::Error>>;
+    /// The invocations this edge starts, edges that only route return one [`Dispatch`] without
+    /// input per next node.
+    fn dispatch(&self, request: &Request<S>) -> BoxFuture<Result<Vec<Dispatch>, crate::Error>> {
+        let next_nodes = self.next_nodes(request);
+        Box::pin(async move { Ok(next_nodes.await?.into_iter().map(Dispatch::to).collect()) })
+    }
     fn neighbours(&self) -> HashSet<NodeKey>;
     fn description(&self) -> String;
 }
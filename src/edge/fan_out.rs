@@ -0,0 +1,164 @@
+use std::{collections::HashSet, marker::PhantomData, sync::Arc};
+
+use futures::future::{BoxFuture, ready};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    JsonValue,
+    edge::{Edge, IntoEdge},
+    node::NodeKey,
+    request::{FromRequest, Request},
+};
+
+/// One invocation of a node, optionally with an input of its own that the node reads through
+/// [`Input`](crate::request::Input).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dispatch {
+    pub node: NodeKey,
+    pub input: Option<JsonValue>,
+}
+
+impl Dispatch {
+    /// Route to `node`, like a plain edge does.
+    pub fn to<K: Into<NodeKey>>(node: K) -> Self {
+        Dispatch {
+            node: node.into(),
+            input: None,
+        }
+    }
+    /// Start `node` with `input`, every dispatch with an input is a separate invocation.
+    pub fn new<K: Into<NodeKey>>(node: K, input: JsonValue) -> Self {
+        Dispatch {
+            node: node.into(),
+            input: Some(input),
+        }
+    }
+}
+
+/// What a [`FanOut`] function may return.
+pub trait IntoDispatches {
+    fn into_dispatches(self) -> Result<Vec<Dispatch>, crate::Error>;
+}
+
+impl IntoDispatches for Vec<Dispatch> {
+    fn into_dispatches(self) -> Result<Vec<Dispatch>, crate::Error> {
+        Ok(self)
+    }
+}
+
+impl<E> IntoDispatches for Result<Vec<Dispatch>, E>
+where
+    crate::Error: From<E>,
+{
+    fn into_dispatches(self) -> Result<Vec<Dispatch>, crate::Error> {
+        self.map_err(crate::Error::from)
+    }
+}
+
+/// An edge that starts its targets any number of times, each with its own input, for
+/// map-reduce style fan-out.
+///
+/// `f` takes extractors like a node function and returns the [`Dispatch`]es, which may only
+/// name `targets`. Dispatches with an input skip the join of their node.
+pub struct FanOut<F> {
+    pub f: F,
+    pub targets: HashSet<NodeKey>,
+}
+
+impl<F> FanOut<F> {
+    pub fn new<I, K>(targets: I, f: F) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<NodeKey>,
+    {
+        FanOut {
+            f,
+            targets: targets.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+struct FanOutEdge<S> {
+    #[allow(clippy::type_complexity)]
+    f: Arc<dyn Fn(&Request<S>) -> Result<Vec<Dispatch>, crate::Error> + Send + Sync>,
+    targets: HashSet<NodeKey>,
+}
+
+impl<S> FanOutEdge<S> {
+    fn checked_dispatches(&self, request: &Request<S>) -> Result<Vec<Dispatch>, crate::Error> {
+        let dispatches = (self.f)(request)?;
+        if let Some(dispatch) = dispatches
+            .iter()
+            .find(|dispatch| !self.targets.contains(&dispatch.node))
+        {
+            return Err(crate::GraphError::UndefinedRoute(dispatch.node.to_string()).into());
+        }
+        Ok(dispatches)
+    }
+}
+
+impl<S> Edge<S> for FanOutEdge<S>
+where
+    S: Send + Sync + 'static,
+{
+    fn next_nodes(
+        &self,
+        request: &Request<S>,
+    ) -> BoxFuture<Result<HashSet<NodeKey>, crate::Error>> {
+        let result = self.checked_dispatches(request).map(|dispatches| {
+            dispatches
+                .into_iter()
+                .map(|dispatch| dispatch.node)
+                .collect()
+        });
+        Box::pin(ready(result))
+    }
+    fn dispatch(&self, request: &Request<S>) -> BoxFuture<Result<Vec<Dispatch>, crate::Error>> {
+        Box::pin(ready(self.checked_dispatches(request)))
+    }
+    fn neighbours(&self) -> HashSet<NodeKey> {
+        self.targets.clone()
+    }
+    fn description(&self) -> String {
+        format!("Fan Out Edge to [{:?}]", self.targets)
+    }
+}
+
+pub struct FanOutAdapter<Args, Output>(PhantomData<fn(Args) -> Output>);
+
+macro_rules! impl_for {
+    ($($T: ident)*) => {
+        impl_for!(@unfold [] [$($T)*]);
+    };
+    (@impl $($T: ident)*) => {
+        impl<$( $T, )* Output, S, F> IntoEdge<S, FanOutAdapter<($($T,)*), Output>> for FanOut<F>
+        where F: Fn($($T,)*) -> Output + Send + Sync + 'static,
+        Output: IntoDispatches,
+        S: Send + Sync + 'static,
+        $( $T: FromRequest<S>, )*
+        {
+            #[allow(unused_variables, non_snake_case)]
+            fn into_edge(self) -> std::sync::Arc<dyn Edge<S>> {
+                let f = self.f;
+                std::sync::Arc::new(FanOutEdge::<S> {
+                    f: Arc::new(move |request: &Request<S>| {
+                        $(
+                        let $T = $T::from_request(request)?;
+                        )*
+                        f($($T,)*).into_dispatches()
+                    }),
+                    targets: self.targets,
+                }) as std::sync::Arc<dyn Edge<S>>
+            }
+        }
+    };
+    (@unfold [$($T: ident)*] []) => {
+        impl_for!(@impl $($T)*);
+    };
+    (@unfold [$($T: ident)*] [$TN: ident $($TRest: ident)*]) => {
+        impl_for!(@impl $($T)* );
+        impl_for!(@unfold [$($T)* $TN] [$($TRest)*]);
+    };
+}
+
+impl_for!(T0 T1 T2 T3 T4 T5 T6 T7 T8 T9 T10 T11 T12 T13 T14 T15);
@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+
+use futures::future::{BoxFuture, ready};
+
+use crate::{
+    GraphError,
+    edge::{Edge, IntoEdge},
+    node::NodeKey,
+    request::Request,
+};
+
+/// Routes to the nodes named by the [`Command`](crate::node::Command) its node returned.
+///
+/// `targets` are all the nodes the commands may name, a command naming another node fails with
+/// [`GraphError::UndefinedRoute`].
+#[derive(Debug, Clone)]
+pub struct Goto {
+    pub targets: HashSet<NodeKey>,
+}
+
+impl Goto {
+    pub fn new<I, K>(targets: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<NodeKey>,
+    {
+        Goto {
+            targets: targets.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<S> Edge<S> for Goto
+where
+    S: Send + Sync + 'static,
+{
+    fn next_nodes(
+        &self,
+        request: &Request<S>,
+    ) -> BoxFuture<Result<HashSet<NodeKey>, crate::Error>> {
+        let result = (|| {
+            let goto = request.goto.get().ok_or(GraphError::MissingCommand)?;
+            if let Some(node_key) = goto
+                .iter()
+                .find(|node_key| !self.targets.contains(node_key))
+            {
+                return Err(GraphError::UndefinedRoute(node_key.to_string()).into());
+            }
+            Ok(goto.into_iter().collect())
+        })();
+        Box::pin(ready(result))
+    }
+    fn neighbours(&self) -> HashSet<NodeKey> {
+        self.targets.clone()
+    }
+    fn description(&self) -> String {
+        format!("Goto Edge to [{:?}]", self.targets)
+    }
+}
+
+impl<S> IntoEdge<S, Goto> for Goto
+where
+    S: Send + Sync + 'static,
+{
+    fn into_edge(self) -> std::sync::Arc<dyn Edge<S>> {
+        std::sync::Arc::new(self) as std::sync::Arc<dyn Edge<S>>
+    }
+}
@@ -0,0 +1,78 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::Arc,
+};
+
+use crate::{
+    Error,
+    request::{FromRequest, Request},
+};
+
+/// Values of the [`Context`](crate::Context) by type, the dependencies of nodes that don't know
+/// the type of the application context. Take one in a node with [`Extension`].
+#[derive(Clone, Default)]
+pub struct Extensions(Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>);
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add `value`, replacing the one of the same type if there is one.
+    pub fn insert<T>(&mut self, value: T)
+    where
+        T: Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.0).insert(TypeId::of::<T>(), Arc::new(value));
+    }
+    pub fn get<T>(&self) -> Option<&T>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+    pub fn contains<T>(&self) -> bool
+    where
+        T: Send + Sync + 'static,
+    {
+        self.0.contains_key(&TypeId::of::<T>())
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+/// A clone of the extension of type `T` of the context, see [`Context::with_extension`](crate::Context::with_extension).
+#[derive(Debug, Clone, Default)]
+pub struct Extension<T>(pub T);
+
+impl<S, T> FromRequest<S> for Extension<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn from_request(request: &Request<S>) -> Result<Self, Error> {
+        request
+            .context
+            .extensions
+            .get::<T>()
+            .cloned()
+            .map(Extension)
+            .ok_or_else(|| Error::ExtractError {
+                extractor: "Extension",
+                reason: format!(
+                    "the context has no extension of type {}",
+                    std::any::type_name::<T>()
+                ),
+            })
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Error, JsonValue,
+    checkpoint::Checkpoint,
+    node::NodeKey,
+    request::{FromRequest, Request},
+};
+
+/// Raised by a node to pause the run, return it as the error of the node.
+///
+/// The node runs again when the run is resumed, and can read the value it was resumed with
+/// through [`Resume`]. A subgraph node instead continues its own run from where it paused.
+#[derive(Debug, Clone)]
+pub struct Interrupt {
+    /// Shown to whoever resumes the run, the question to answer for example.
+    pub payload: JsonValue,
+    /// Where the subgraph that raised it paused, kept in the checkpoint of the parent.
+    pub(crate) subgraph: Option<Box<Checkpoint>>,
+}
+
+impl Interrupt {
+    pub fn new(payload: JsonValue) -> Self {
+        Interrupt {
+            payload,
+            subgraph: None,
+        }
+    }
+    /// The interrupt raised by a node, if `error` is one.
+    pub fn from_error(error: &Error) -> Option<&Interrupt> {
+        match error {
+            Error::NodeExecutionError(error) => error.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Interrupt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted with {}", self.payload)
+    }
+}
+
+impl std::error::Error for Interrupt {}
+
+/// Where a run was interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterruptKind {
+    /// Before the node ran, see [`Graph::interrupt_before`](crate::Graph::interrupt_before).
+    Before,
+    /// After the node completed, see [`Graph::interrupt_after`](crate::Graph::interrupt_after).
+    After,
+    /// The node raised an [`Interrupt`].
+    Node,
+}
+
+/// A run that stopped at an interrupt, see [`Response::interrupted`](crate::Response::interrupted).
+#[derive(Debug, Clone)]
+pub struct Interrupted {
+    pub node: NodeKey,
+    pub kind: InterruptKind,
+    /// The payload of a node raised [`Interrupt`].
+    pub payload: Option<JsonValue>,
+    /// Continue the run from here with [`Graph::run_from_checkpoint`](crate::Graph::run_from_checkpoint),
+    /// it is also saved if the graph has a checkpointer.
+    pub checkpoint: Checkpoint,
+}
+
+impl Interrupted {
+    /// The nodes that run when the run is resumed.
+    pub fn frontier(&self) -> &[NodeKey] {
+        &self.checkpoint.frontier
+    }
+}
+
+/// The value a run was resumed with, see [`RunConfig::resume`](crate::request::RunConfig::resume).
+/// `None` in the nodes other than the one the run was interrupted at.
+#[derive(Debug, Clone, Default)]
+pub struct Resume(pub Option<JsonValue>);
+
+impl<S> FromRequest<S> for Resume {
+    fn from_request(request: &Request<S>) -> Result<Self, Error> {
+        Ok(Resume(request.config.resume.clone()))
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{Graph, node::NodeKey};
+
+/// How a node with several incoming edges is triggered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Join {
+    /// Run every time any predecessor routes to the node.
+    #[default]
+    Any,
+    /// Wait for every predecessor that can still arrive in the current wave, then run once.
+    All,
+    /// Wait for the named predecessors only, then run once.
+    Subset(HashSet<NodeKey>),
+}
+
+impl Join {
+    pub fn subset<I, K>(predecessors: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<NodeKey>,
+    {
+        Join::Subset(predecessors.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Tracks which predecessors have arrived at the joining nodes of one run.
+///
+/// A wave ends for a joining node once every required predecessor has either arrived or can
+/// no longer be reached from the nodes that are still running or waiting.
+#[derive(Debug, Default)]
+pub(crate) struct JoinTracker {
+    arrived: HashMap<NodeKey, HashSet<NodeKey>>,
+}
+
+impl JoinTracker {
+    /// Records that `from` routed to `to`, returns `true` if `to` can be scheduled right away.
+    pub fn arrive<S>(&mut self, graph: &Graph<S>, from: &NodeKey, to: &NodeKey) -> bool {
+        match graph.joins.get(to) {
+            None | Some(Join::Any) => true,
+            Some(_) => {
+                self.arrived
+                    .entry(to.clone())
+                    .or_default()
+                    .insert(from.clone());
+                false
+            }
+        }
+    }
+    /// Parks `node_key` at its join without an arrival, used when restoring a checkpoint.
+    pub fn wait(&mut self, node_key: NodeKey) {
+        self.arrived.entry(node_key).or_default();
+    }
+    /// The nodes waiting at their join.
+    pub fn waiting(&self) -> impl Iterator<Item = &NodeKey> {
+        self.arrived.keys()
+    }
+    /// Takes the waiting nodes whose wave is complete. `running` are the nodes with an invocation
+    /// running or waiting to run.
+    pub fn take_ready<'a, S>(
+        &mut self,
+        graph: &Graph<S>,
+        running: impl IntoIterator<Item = &'a NodeKey>,
+    ) -> Vec<NodeKey> {
+        let running: Vec<&NodeKey> = running.into_iter().collect();
+        let mut ready = Vec::new();
+        for (node_key, arrived) in &self.arrived {
+            let required = match graph.joins.get(node_key) {
+                Some(Join::Subset(subset)) => subset.clone(),
+                _ => graph.predecessors(node_key),
+            };
+            let sources: Vec<&NodeKey> = running
+                .iter()
+                .copied()
+                .chain(self.arrived.keys().filter(|key| *key != node_key))
+                .collect();
+            // a predecessor that still runs, another branch of a fan out for example, can arrive
+            // again even if it already did
+            let is_ready = required.iter().all(|predecessor| {
+                !running.contains(&predecessor)
+                    && (arrived.contains(predecessor)
+                        || !sources
+                            .iter()
+                            .any(|source| graph.can_reach(source, predecessor, node_key)))
+            });
+            if is_ready {
+                ready.push(node_key.clone());
+            }
+        }
+        for node_key in &ready {
+            self.arrived.remove(node_key);
+        }
+        ready
+    }
+    /// Releases every waiting node, used when nothing else is left to run.
+    pub fn take_all(&mut self) -> Vec<NodeKey> {
+        self.arrived.drain().map(|(node_key, _)| node_key).collect()
+    }
+}
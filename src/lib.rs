@@ -8,24 +8,68 @@ use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use thiserror::Error;
 
 use crate::{
+    checkpoint::Checkpointer,
     edge::{Edge, IntoEdge},
-    node::{IntoNode, Node, NodeKey},
+    extension::Extensions,
+    interrupt::{Interrupt, Interrupted},
+    join::Join,
+    node::{IntoNode, Node, NodeKey, NodeOptions},
     request::Request,
-    state::State,
+    run::{ExecutionMode, NodeExecution, RunRecorder, Transition},
+    state::{Reducer, Reducers, State, StatePatch},
+    typed::json::TypedState,
 };
 
+pub mod cancel;
+pub mod checkpoint;
 pub mod edge;
 pub mod ext;
+pub mod extension;
+pub mod interrupt;
+pub mod join;
 pub mod node;
 pub mod request;
+pub mod retry;
+pub mod run;
 pub mod state;
+pub mod stream;
 pub mod typed;
 pub mod utils;
 
+pub use crabgraph_macros::GraphState;
+pub use typed::schema::GraphState;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use modify::Modification;
+    pub use serde_json;
+    pub use tracing;
+}
+
 pub trait TransferObject: Sized + Serialize + DeserializeOwned + Send + Sync + 'static {}
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Context<S> {
     pub state: S,
+    /// Values handed to nodes by type, see [`Extension`](extension::Extension).
+    #[serde(skip)]
+    pub extensions: Extensions,
+}
+
+impl<S> Context<S> {
+    pub fn new(state: S) -> Self {
+        Context {
+            state,
+            extensions: Extensions::default(),
+        }
+    }
+    /// Add `value` to the extensions, replacing the one of the same type if there is one.
+    pub fn with_extension<T>(mut self, value: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.extensions.insert(value);
+        self
+    }
 }
 
 impl<S> Context<S>
@@ -36,14 +80,46 @@ where
         Request {
             context: self.clone(),
             state,
+            config: Default::default(),
+            failure: None,
+            input: None,
+            goto: Default::default(),
+            snapshot: None,
+            subgraph: None,
         }
     }
 }
 
 pub type JsonValue = serde_json::Value;
 pub type JsonObject = serde_json::Map<String, JsonValue>;
+/// The outcome of [`Graph::run`].
+#[derive(Debug, Clone)]
 pub struct Response {
+    /// The final state of the run.
     pub state: JsonValue,
+    /// Every node execution in completion order.
+    pub executions: Vec<NodeExecution>,
+    /// Every edge taken, in the order the edges were resolved.
+    pub path: Vec<Transition>,
+    /// Every change the nodes made to the state, in order. Applied onto the state the run started
+    /// from, see [`State::apply_patch`], they rebuild its final state.
+    pub patches: Vec<StatePatch>,
+    /// Set if the run stopped at an interrupt instead of reaching its end.
+    pub interrupted: Option<Interrupted>,
+}
+
+impl Response {
+    /// The edges that routed to [`NodeKey::End`].
+    pub fn terminal_edges(&self) -> impl Iterator<Item = &Transition> {
+        self.path.iter().filter(|t| t.to == NodeKey::End)
+    }
+}
+
+/// A [`Response`] with the final state deserialized into `T`.
+#[derive(Debug, Clone)]
+pub struct TypedResponse<T> {
+    pub output: T,
+    pub response: Response,
 }
 #[derive(Debug, Error)]
 pub enum Error {
@@ -61,6 +137,30 @@ pub enum Error {
     },
     #[error("Node execution error: {0}")]
     NodeExecutionError(#[from] NodeError),
+    #[error("Node {node_key} timed out after {timeout:?}")]
+    NodeTimeout {
+        node_key: NodeKey,
+        timeout: std::time::Duration,
+    },
+    #[error("Run deadline exceeded at {node_key}")]
+    DeadlineExceeded { node_key: NodeKey },
+    #[error("Run cancelled, nodes in flight: [{}]", join_keys(.in_flight, ", "))]
+    Cancelled { in_flight: HashSet<NodeKey> },
+    #[error("Checkpoint error: {0}")]
+    CheckpointError(#[source] NodeError),
+    #[error("Cannot extract {extractor}: {reason}")]
+    ExtractError {
+        extractor: &'static str,
+        reason: String,
+    },
+    #[error("Nodes {first} and {second} both wrote {key} at the same time, it needs a reducer")]
+    InvalidConcurrentUpdate {
+        key: String,
+        first: NodeKey,
+        second: NodeKey,
+    },
+    #[error("Patch error: {0}")]
+    PatchError(#[from] json_patch::PatchError),
 }
 
 pub type NodeError = Box<dyn std::error::Error + Send + Sync>;
@@ -79,18 +179,72 @@ pub enum GraphError {
     EmptyEdge { from: NodeKey, description: String },
     #[error("Graph cannot reach End node")]
     UnreachableEndNode,
+    #[error(
+        "Recursion limit of {limit} reached while scheduling {node}, recent steps: {}",
+        join_keys(.history, " -> ")
+    )]
+    RecursionLimitExceeded {
+        limit: usize,
+        node: NodeKey,
+        history: Vec<NodeKey>,
+    },
+    #[error("Join of {node} waits for {predecessor}, which has no edge to it")]
+    InvalidJoin { node: NodeKey, predecessor: NodeKey },
+    #[error("Node {node} uses undefined resource {resource}")]
+    UndefinedResource { node: NodeKey, resource: String },
+    #[error("Goto edge without a command, the node must return a Command")]
+    MissingCommand,
+}
+
+fn join_keys<'a>(keys: impl IntoIterator<Item = &'a NodeKey>, separator: &str) -> String {
+    keys.into_iter()
+        .map(NodeKey::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
 }
 
 pub struct Graph<S> {
     pub nodes: HashMap<NodeKey, Arc<dyn Node<S>>>,
+    pub node_options: HashMap<NodeKey, NodeOptions>,
     pub edges: HashMap<NodeKey, Vec<Arc<dyn Edge<S>>>>,
+    /// Handler node of each node whose failures are routed instead of ending the run.
+    pub error_edges: HashMap<NodeKey, NodeKey>,
+    pub joins: HashMap<NodeKey, Join>,
+    pub mode: ExecutionMode,
+    pub recursion_limit: Option<usize>,
+    /// Default maximum number of nodes running at once within a run.
+    pub max_concurrency: Option<usize>,
+    /// Named semaphores shared by every run of the graph.
+    pub resources: HashMap<String, Arc<tokio::sync::Semaphore>>,
+    pub checkpointer: Option<Arc<dyn Checkpointer>>,
+    pub interrupt_before: HashSet<NodeKey>,
+    pub interrupt_after: HashSet<NodeKey>,
+    pub reducers: Reducers,
+    /// JSON schema of the state, see [`Graph::set_state`].
+    pub state_schema: Option<schemars::Schema>,
+    /// Fail runs where nodes running at the same time write the same key, see
+    /// [`Graph::set_detect_conflicts`].
+    pub detect_conflicts: bool,
 }
 
 impl<S> Default for Graph<S> {
     fn default() -> Self {
         Self {
             nodes: HashMap::new(),
+            node_options: HashMap::new(),
             edges: HashMap::new(),
+            error_edges: HashMap::new(),
+            joins: HashMap::new(),
+            mode: ExecutionMode::default(),
+            recursion_limit: None,
+            max_concurrency: None,
+            resources: HashMap::new(),
+            checkpointer: None,
+            interrupt_before: HashSet::new(),
+            interrupt_after: HashSet::new(),
+            reducers: Reducers::default(),
+            state_schema: None,
+            detect_conflicts: false,
         }
     }
 }
@@ -99,11 +253,53 @@ impl<S> Clone for Graph<S> {
     fn clone(&self) -> Self {
         Self {
             nodes: self.nodes.clone(),
+            node_options: self.node_options.clone(),
             edges: self.edges.clone(),
+            error_edges: self.error_edges.clone(),
+            joins: self.joins.clone(),
+            mode: self.mode,
+            recursion_limit: self.recursion_limit,
+            max_concurrency: self.max_concurrency,
+            resources: self.resources.clone(),
+            checkpointer: self.checkpointer.clone(),
+            interrupt_before: self.interrupt_before.clone(),
+            interrupt_after: self.interrupt_after.clone(),
+            reducers: self.reducers.clone(),
+            state_schema: self.state_schema.clone(),
+            detect_conflicts: self.detect_conflicts,
         }
     }
 }
 
+impl<S> Graph<S> {
+    /// All nodes with an edge that may route to `node_key`.
+    pub fn predecessors(&self, node_key: &NodeKey) -> HashSet<NodeKey> {
+        self.edges
+            .iter()
+            .filter(|(_, edges)| edges.iter().any(|e| e.neighbours().contains(node_key)))
+            .map(|(from, _)| from.clone())
+            .collect()
+    }
+    /// Whether `to` can be reached from `from` without passing through `avoid`.
+    pub fn can_reach(&self, from: &NodeKey, to: &NodeKey, avoid: &NodeKey) -> bool {
+        let mut visited = HashSet::new();
+        let mut queue = vec![from.clone()];
+        while let Some(node_key) = queue.pop() {
+            if &node_key == to {
+                return true;
+            }
+            if &node_key == avoid || !visited.insert(node_key.clone()) {
+                continue;
+            }
+            for edge in self.edges.get(&node_key).into_iter().flatten() {
+                queue.extend(edge.neighbours());
+            }
+            queue.extend(self.error_edges.get(&node_key).cloned());
+        }
+        false
+    }
+}
+
 impl<S> Graph<S>
 where
     S: Clone + Send + Sync + 'static,
@@ -131,7 +327,118 @@ where
         self.nodes.insert(key.into(), node.into_node());
         self
     }
+    pub fn add_node_with<K: Into<NodeKey>, N: IntoNode<S, A>, A>(
+        &mut self,
+        key: K,
+        node: N,
+        options: NodeOptions,
+    ) -> &mut Self {
+        let key = key.into();
+        self.node_options.insert(key.clone(), options);
+        self.nodes.insert(key, node.into_node());
+        self
+    }
+    /// Route failures of `from` to `handler` instead of failing the run.
+    ///
+    /// A failure is routed once the retries of the node are exhausted, the normal out edges of
+    /// the failed node are not taken. The handler reads what went wrong through
+    /// [`NodeFailure`](crate::request::NodeFailure).
+    pub fn add_error_edge<F: Into<NodeKey>, H: Into<NodeKey>>(
+        &mut self,
+        from: F,
+        handler: H,
+    ) -> &mut Self {
+        self.error_edges.insert(from.into(), handler.into());
+        self
+    }
+    /// Declare how a node with several incoming edges waits for its predecessors.
+    pub fn set_join<K: Into<NodeKey>>(&mut self, key: K, join: Join) -> &mut Self {
+        self.joins.insert(key.into(), join);
+        self
+    }
+    pub fn set_mode(&mut self, mode: ExecutionMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+    /// Default limit of node executions (or supersteps) per run, see [`RunConfig`](crate::request::RunConfig).
+    pub fn set_recursion_limit(&mut self, limit: usize) -> &mut Self {
+        self.recursion_limit = Some(limit);
+        self
+    }
+    /// Default limit of nodes running at once per run, see [`RunConfig`](crate::request::RunConfig).
+    pub fn set_max_concurrency(&mut self, max_concurrency: usize) -> &mut Self {
+        self.max_concurrency = Some(max_concurrency);
+        self
+    }
+    /// Fail the run with [`Error::InvalidConcurrentUpdate`] when two nodes of the same superstep,
+    /// or two nodes running at the same time in async mode, write a key without a reducer.
+    pub fn set_detect_conflicts(&mut self, detect_conflicts: bool) -> &mut Self {
+        self.detect_conflicts = detect_conflicts;
+        self
+    }
+    /// Declare a resource with `permits` slots, nodes take a permit of the resources in their
+    /// [`NodeOptions`] before they start.
+    pub fn add_resource(&mut self, name: impl Into<String>, permits: usize) -> &mut Self {
+        self.resources
+            .insert(name.into(), Arc::new(tokio::sync::Semaphore::new(permits)));
+        self
+    }
+    /// Combine the writes to the top-level state `key` with `reducer`, instead of replacing the
+    /// value. Writes from parallel nodes and supersteps then merge predictably.
+    ///
+    /// A modification sees the key as unset, what it writes there is the update for the reducer.
+    pub fn add_reducer<R: Reducer>(&mut self, key: impl Into<String>, reducer: R) -> &mut Self {
+        self.reducers.insert(key.into(), Arc::new(reducer));
+        self
+    }
+    /// Use `T` as the schema of the state, its reducers are added to the graph.
+    pub fn set_state<T: GraphState>(&mut self) -> &mut Self {
+        for (key, reducer) in T::reducers().iter() {
+            self.reducers.insert(key.to_string(), reducer.clone());
+        }
+        self.state_schema = Some(T::schema());
+        self
+    }
+    /// Save a checkpoint after every node (or superstep) of runs that have a
+    /// [`thread_id`](crate::request::RunConfig::thread_id).
+    pub fn set_checkpointer<C: Checkpointer>(&mut self, checkpointer: C) -> &mut Self {
+        self.checkpointer = Some(Arc::new(checkpointer));
+        self
+    }
+    /// Stop the run before `key` runs, see [`Response::interrupted`].
+    pub fn interrupt_before<K: Into<NodeKey>>(&mut self, key: K) -> &mut Self {
+        self.interrupt_before.insert(key.into());
+        self
+    }
+    /// Stop the run once `key` completed, see [`Response::interrupted`].
+    pub fn interrupt_after<K: Into<NodeKey>>(&mut self, key: K) -> &mut Self {
+        self.interrupt_after.insert(key.into());
+        self
+    }
     pub fn check(&self) -> Result<(), GraphError> {
+        for (node_key, options) in &self.node_options {
+            if let Some(resource) = options
+                .resources
+                .iter()
+                .find(|resource| !self.resources.contains_key(*resource))
+            {
+                return Err(GraphError::UndefinedResource {
+                    node: node_key.clone(),
+                    resource: resource.clone(),
+                });
+            }
+        }
+        for (node_key, join) in &self.joins {
+            if let Join::Subset(subset) = join {
+                let predecessors = self.predecessors(node_key);
+                if let Some(predecessor) = subset.difference(&predecessors).next() {
+                    return Err(GraphError::InvalidJoin {
+                        node: node_key.clone(),
+                        predecessor: predecessor.clone(),
+                    });
+                }
+            }
+        }
         let mut checked = HashSet::new();
         let mut next_to_check = HashSet::new();
         next_to_check.insert(NodeKey::Start);
@@ -161,6 +468,12 @@ where
                     }
                     neighbours.extend(neighbours_for_edge);
                 }
+                if let Some(handler) = self.error_edges.get(&node_key) {
+                    if handler == &NodeKey::Start {
+                        return Err(GraphError::PointToStart);
+                    }
+                    neighbours.insert(handler.clone());
+                }
                 checked.insert(node_key.clone());
                 let unchecked_neighbours: HashSet<_> =
                     neighbours.difference(&checked).cloned().collect();
@@ -180,69 +493,21 @@ where
         }
         Ok(())
     }
-    pub async fn run(self: Arc<Self>, request: Request<S>) -> Result<(), Error> {
-        struct TaskCompleted {
-            result: Result<(), Error>,
-            node_key: NodeKey,
-        }
-        let mut task_set = tokio::task::JoinSet::new();
-        task_set.spawn(futures::future::ready(
-            // start trigger task
-            TaskCompleted {
-                result: Ok(()),
-                node_key: NodeKey::Start,
-            },
-        ));
-        loop {
-            enum Event {
-                TaskCompleted(TaskCompleted),
-            }
-            let event = tokio::select! {
-                result = task_set.join_next(), if !task_set.is_empty() => {
-                    Event::TaskCompleted(result.expect("not empty set")?)
-                    // Handle the result of the completed task
-                }
-                else => {
-                    // All tasks completed
-                    break;
-                }
-            };
-            match event {
-                Event::TaskCompleted(TaskCompleted { result, node_key }) => {
-                    result?;
-                    let edges = self
-                        .edges
-                        .get(&node_key)
-                        .filter(|e| !e.is_empty())
-                        .ok_or_else(|| GraphError::MissingOutEdge(node_key.clone()))?;
-                    tracing::info!(%node_key, "Node completed");
-                    let request = request.clone();
-                    for e in edges {
-                        for to_node_key in e.next_nodes(&request).await.map_err(|e| {
-                            Error::ResolveNextNodesError {
-                                error: Box::new(e),
-                                node_key: node_key.clone(),
-                            }
-                        })? {
-                            if to_node_key == NodeKey::End {
-                            } else {
-                                let node = self.nodes.get(&to_node_key).ok_or_else(|| {
-                                    GraphError::UndefinedNode(to_node_key.clone())
-                                })?;
-                                let fut = node.clone().call(request.clone());
-                                let node_key = to_node_key.clone();
-                                task_set.spawn(async move {
-                                    let result = fut.await;
-
-                                    TaskCompleted { result, node_key }
-                                });
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        Ok(())
+    pub async fn run(self: Arc<Self>, request: Request<S>) -> Result<Response, Error> {
+        self.execute(request, RunRecorder::new(), None).await
+    }
+    /// Like [`Graph::run`], but also deserializes the final state through [`TypedState`].
+    pub async fn run_typed<T>(
+        self: Arc<Self>,
+        request: Request<S>,
+    ) -> Result<TypedResponse<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let state = request.state.clone();
+        let response = self.run(request).await?;
+        let output = state.fetch_view(TypedState::<T>::new()).await?;
+        Ok(TypedResponse { output, response })
     }
     pub fn compile(self) -> Result<Arc<Self>, GraphError> {
         self.check()?;
@@ -254,7 +519,28 @@ impl<S> Node<S> for Graph<S>
 where
     S: Clone + Send + Sync + 'static,
 {
-    fn call(self: Arc<Self>, request: Request<S>) -> BoxFuture<'static, Result<(), Error>> {
-        Box::pin(async move { self.run(request).await })
+    fn call(self: Arc<Self>, mut request: Request<S>) -> BoxFuture<'static, Result<(), Error>> {
+        Box::pin(async move {
+            let response = match request.subgraph.take() {
+                // continue where the subgraph paused, on the state the parent resumed with
+                Some(mut checkpoint) => {
+                    checkpoint.state = request.state.snapshot().await;
+                    self.run_from_checkpoint(request, *checkpoint).await?
+                }
+                // the resume value is for the subgraph node, not for the nodes inside
+                None => {
+                    request.config.resume = None;
+                    self.run(request).await?
+                }
+            };
+            match response.interrupted {
+                // pause the parent too, the subgraph continues from its checkpoint on resume
+                Some(interrupted) => Err(Error::NodeExecutionError(Box::new(Interrupt {
+                    payload: interrupted.payload.unwrap_or_default(),
+                    subgraph: Some(Box::new(interrupted.checkpoint)),
+                }))),
+                None => Ok(()),
+            }
+        })
     }
 }
@@ -1,8 +1,13 @@
-use std::{borrow::Cow, fmt::Display, ops::Deref, sync::Arc};
+use std::{
+    borrow::Cow, collections::BTreeSet, fmt::Display, ops::Deref, sync::Arc, time::Duration,
+};
 
 use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
 
-use crate::Request;
+use crate::{Request, retry::RetryPolicy};
+mod command;
+pub use command::Command;
 mod function;
 pub use function::NodeFunction;
 mod sequence;
@@ -27,6 +32,35 @@ where
     }
 }
 
+/// Options of a node, set with [`Graph::add_node_with`](crate::Graph::add_node_with).
+#[derive(Debug, Clone, Default)]
+pub struct NodeOptions {
+    /// The node fails with [`Error::NodeTimeout`](crate::Error::NodeTimeout) if it runs longer.
+    pub timeout: Option<Duration>,
+    pub retry: Option<RetryPolicy>,
+    /// Resources of the graph this node takes a permit of while it runs, see
+    /// [`Graph::add_resource`](crate::Graph::add_resource).
+    pub resources: BTreeSet<String>,
+}
+
+impl NodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+    pub fn with_resource(mut self, resource: impl Into<String>) -> Self {
+        self.resources.insert(resource.into());
+        self
+    }
+}
+
 pub trait IntoNode<S, A> {
     fn into_node(self) -> Arc<dyn Node<S>>;
 }
@@ -57,7 +91,7 @@ where
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub enum NodeKey {
     Named(Cow<'static, str>),
     Start,
@@ -88,6 +122,23 @@ impl Deref for NodeKey {
     }
 }
 
+impl Serialize for NodeKey {
+    fn serialize<Se: serde::Serializer>(&self, serializer: Se) -> Result<Se::Ok, Se::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(match name.as_str() {
+            "@start" => NodeKey::Start,
+            "@end" => NodeKey::End,
+            _ => NodeKey::from(name),
+        })
+    }
+}
+
 impl From<&'static str> for NodeKey {
     fn from(val: &'static str) -> Self {
         NodeKey::Named(Cow::Borrowed(val))
@@ -0,0 +1,46 @@
+use modify::Modification;
+
+use crate::{JsonObject, node::NodeKey, request::Request, state::StateUpdate};
+
+/// Returned by a node to update the state and choose the next nodes in one go.
+///
+/// Route the node with a [`Goto`](crate::edge::Goto) edge listing every node its commands may
+/// go to, so [`Graph::check`](crate::Graph::check) still knows the targets.
+#[derive(Debug, Default)]
+pub struct Command {
+    pub update: Option<StateUpdate>,
+    pub goto: Vec<NodeKey>,
+}
+
+impl Command {
+    pub fn goto<K: Into<NodeKey>>(node: K) -> Self {
+        Command {
+            update: None,
+            goto: vec![node.into()],
+        }
+    }
+    pub fn goto_all<I, K>(nodes: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<NodeKey>,
+    {
+        Command {
+            update: None,
+            goto: nodes.into_iter().map(Into::into).collect(),
+        }
+    }
+    pub fn with_update<M>(mut self, modification: M) -> Self
+    where
+        M: Modification<JsonObject> + Send + 'static,
+    {
+        self.update = Some(StateUpdate::new(modification));
+        self
+    }
+    /// Apply the update to the state of `request` and hand the targets to its edges.
+    pub(crate) async fn apply<S>(self, request: &Request<S>) {
+        if let Some(update) = self.update {
+            request.state.apply_modification(update).await;
+        }
+        request.goto.set(self.goto);
+    }
+}
@@ -3,8 +3,9 @@ use std::{marker::PhantomData, pin::Pin};
 use futures::future::BoxFuture;
 
 use crate::{
-    node::{IntoNode, Node},
+    node::{Command, IntoNode, Node},
     request::{FromRequest, Request},
+    state::IntoStateModification,
 };
 
 pub struct NodeFunction<F>(pub F);
@@ -26,6 +27,10 @@ pub struct AsyncFunctionAdapter<Args, Fut, Output>(
     pub fn() -> Output,
 );
 
+/// Output adapter of node functions returning a modification the run applies, see
+/// [`IntoStateModification`].
+pub struct ModificationOutput<A>(PhantomData<A>);
+
 macro_rules! impl_for {
     ($($T: ident)*) => {
         impl_for!(@unfold [] [$($T)*]);
@@ -42,6 +47,7 @@ macro_rules! impl_for {
                 std::sync::Arc::new(NodeFunction(move |request: Request<S>| {
                     let f = self.clone();
                     Box::pin(async move {
+                        let request = request.snapshotted().await;
                         $(
                             let $T = $T::from_request(&request)?;
                         )*
@@ -52,6 +58,52 @@ macro_rules! impl_for {
                 })) as std::sync::Arc<dyn Node<S>>
             }
         }
+        impl<$( $T, )* Fut, F, S> IntoNode<S, AsyncFunctionAdapter<($($T,)*), Fut, Command>> for F
+        where F: Fn($($T,)*) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = Result<Command, crate::NodeError>> + Send + 'static,
+        S: Send + Sync + Clone + 'static,
+        $( $T: FromRequest<S> + Send + 'static, )*
+        {
+            #[allow(unused_variables, non_snake_case)]
+            fn into_node(self) -> std::sync::Arc<dyn Node<S>> {
+                std::sync::Arc::new(NodeFunction(move |request: Request<S>| {
+                    let f = self.clone();
+                    Box::pin(async move {
+                        let request = request.snapshotted().await;
+                        $(
+                            let $T = $T::from_request(&request)?;
+                        )*
+                        let command = f($($T,)*).await?;
+                        command.apply(&request).await;
+                        Ok(())
+                    }) as BoxFuture<'static, Result<(), crate::Error>>
+                })) as std::sync::Arc<dyn Node<S>>
+            }
+        }
+        impl<$( $T, )* Fut, F, S, A> IntoNode<S, AsyncFunctionAdapter<($($T,)*), Fut, ModificationOutput<A>>> for F
+        where F: Fn($($T,)*) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoStateModification<A>,
+        S: Send + Sync + Clone + 'static,
+        $( $T: FromRequest<S> + Send + 'static, )*
+        {
+            #[allow(unused_variables, non_snake_case)]
+            fn into_node(self) -> std::sync::Arc<dyn Node<S>> {
+                std::sync::Arc::new(NodeFunction(move |request: Request<S>| {
+                    let f = self.clone();
+                    Box::pin(async move {
+                        let request = request.snapshotted().await;
+                        $(
+                            let $T = $T::from_request(&request)?;
+                        )*
+                        if let Some(update) = f($($T,)*).await.into_state()? {
+                            request.state.apply_modification(update).await;
+                        }
+                        Ok(())
+                    }) as BoxFuture<'static, Result<(), crate::Error>>
+                })) as std::sync::Arc<dyn Node<S>>
+            }
+        }
     };
     (@unfold [$($T: ident)*] []) => {
         impl_for!(@impl $($T)*);
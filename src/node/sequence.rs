@@ -1,9 +1,6 @@
 use std::sync::Arc;
 
-use crate::{
-    node::{IntoNode, Node},
-    request::Request,
-};
+use crate::node::{IntoNode, Node};
 #[derive(Default, Clone)]
 pub struct NodeSequence<S>(pub Vec<Arc<dyn Node<S>>>);
 
@@ -34,14 +31,10 @@ where
     ) -> futures::future::BoxFuture<'static, Result<(), crate::Error>> {
         let nodes = self.0.clone();
         Box::pin(async move {
-            let context = request.context;
-            let state = request.state;
-            for (idx, node) in nodes.into_iter().enumerate() {
-                let request = Request {
-                    context: context.clone(),
-                    state: state.clone(),
-                };
-                node.call(request).await?;
+            for node in nodes {
+                // without the snapshot of the request, each node extracts the state the nodes
+                // before it left
+                node.call(request.with_state(request.state.clone())).await?;
             }
             Ok(())
         })
@@ -1,10 +1,160 @@
+use std::{sync::Arc, time::Duration};
 
-use crate::{Context, state::State};
+use serde::de::DeserializeOwned;
+use tokio::time::Instant;
+
+use crate::{Context, JsonObject, cancel::CancellationToken, node::NodeKey, state::State};
 
 #[derive(Debug, Clone, Default)]
 pub struct Request<S> {
     pub context: Context<S>,
     pub state: State,
+    pub config: RunConfig,
+    /// Set when the node was reached through an error edge.
+    pub failure: Option<NodeFailure>,
+    /// Set when the node was started by a [`Dispatch`](crate::edge::Dispatch) with an input.
+    pub input: Option<crate::JsonValue>,
+    pub(crate) goto: GotoSlot,
+    /// The state when the run built the request, what the extractors read.
+    pub(crate) snapshot: Option<Arc<JsonObject>>,
+    /// Where the subgraph run by the node paused, it continues from there.
+    pub(crate) subgraph: Option<Box<crate::checkpoint::Checkpoint>>,
+}
+
+/// Where a node goes next, filled in when it returns a [`Command`](crate::node::Command).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GotoSlot(Arc<std::sync::Mutex<Option<Vec<NodeKey>>>>);
+
+impl GotoSlot {
+    pub(crate) fn new(goto: Option<Vec<NodeKey>>) -> Self {
+        GotoSlot(Arc::new(std::sync::Mutex::new(goto)))
+    }
+    pub(crate) fn set(&self, goto: Vec<NodeKey>) {
+        *self.0.lock().expect("goto poisoned") = Some(goto);
+    }
+    pub(crate) fn get(&self) -> Option<Vec<NodeKey>> {
+        self.0.lock().expect("goto poisoned").clone()
+    }
+    pub(crate) fn take(&self) -> Option<Vec<NodeKey>> {
+        self.0.lock().expect("goto poisoned").take()
+    }
+}
+
+/// Settings for a single run, these take precedence over the defaults of the graph.
+#[derive(Debug, Clone, Default)]
+pub struct RunConfig {
+    /// Maximum number of node executions (or supersteps in superstep mode) of the run.
+    pub recursion_limit: Option<usize>,
+    /// Stops the run once cancelled, see [`CancellationToken`].
+    pub cancellation: CancellationToken,
+    /// The run fails with [`Error::DeadlineExceeded`](crate::Error::DeadlineExceeded) once this
+    /// passes. Inside a node this is the deadline of the node, see [`TimeBudget`].
+    pub deadline: Option<Instant>,
+    /// Maximum number of nodes running at once, overrides [`Graph::max_concurrency`](crate::Graph::max_concurrency).
+    pub max_concurrency: Option<usize>,
+    /// Checkpoints of the run are saved under this thread, if the graph has a
+    /// [`Checkpointer`](crate::checkpoint::Checkpointer).
+    pub thread_id: Option<String>,
+    /// Handed to the invocation a run resumed from a checkpoint was interrupted at, and to no
+    /// other node of the run, see [`Resume`](crate::interrupt::Resume). An interrupted subgraph
+    /// hands it on to the invocation it paused at.
+    pub resume: Option<crate::JsonValue>,
+}
+
+impl RunConfig {
+    pub fn deadline_passed(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+    }
+}
+
+impl<S> Request<S> {
+    pub fn with_recursion_limit(mut self, limit: usize) -> Self {
+        self.config.recursion_limit = Some(limit);
+        self
+    }
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.config.cancellation = cancellation;
+        self
+    }
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.config.deadline = Some(deadline);
+        self
+    }
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.config.max_concurrency = Some(max_concurrency);
+        self
+    }
+    pub fn with_thread_id(mut self, thread_id: impl Into<String>) -> Self {
+        self.config.thread_id = Some(thread_id.into());
+        self
+    }
+    pub fn with_resume(mut self, value: crate::JsonValue) -> Self {
+        self.config.resume = Some(value);
+        self
+    }
+    /// Set the deadline of the run to `timeout` from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+}
+
+impl<S: Clone> Request<S> {
+    /// The same request pointing at another state.
+    pub fn with_state(&self, state: State) -> Self {
+        Request {
+            state,
+            snapshot: None,
+            ..self.clone()
+        }
+    }
+    /// The same request with the extractors reading `snapshot`, a copy of its state.
+    pub(crate) fn with_snapshot(&self, snapshot: Arc<JsonObject>) -> Self {
+        Request {
+            snapshot: Some(snapshot),
+            ..self.clone()
+        }
+    }
+    /// The same request with a snapshot of its state for the extractors, unless it has one.
+    pub(crate) async fn snapshotted(self) -> Self {
+        if self.snapshot.is_some() {
+            return self;
+        }
+        let snapshot = Arc::new(self.state.snapshot().await);
+        Request {
+            snapshot: Some(snapshot),
+            ..self
+        }
+    }
+    /// The same request for a handler of `failure`.
+    pub fn with_failure(&self, failure: Option<NodeFailure>) -> Self {
+        Request {
+            failure,
+            ..self.clone()
+        }
+    }
+    /// The same request for an invocation with its own `input`.
+    pub fn with_input(&self, input: Option<crate::JsonValue>) -> Self {
+        Request {
+            input,
+            ..self.clone()
+        }
+    }
+    /// The same request carrying where the command of a completed node goes.
+    pub(crate) fn with_goto(&self, goto: Option<Vec<NodeKey>>) -> Self {
+        Request {
+            goto: GotoSlot::new(goto),
+            ..self.clone()
+        }
+    }
+}
+
+impl<S> Request<S> {
+    /// Read the snapshot of the state, `None` if the request was not built by the run, see
+    /// [`Request::snapshotted`].
+    pub(crate) fn read_state<R>(&self, read: impl FnOnce(&JsonObject) -> R) -> Option<R> {
+        self.snapshot.as_deref().map(read)
+    }
 }
 
 pub trait FromRequest<S>: Sized {
@@ -34,3 +184,65 @@ where
         Ok(request.clone())
     }
 }
+
+/// The time left for the current node, the earlier of its own timeout and the run deadline.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeBudget {
+    pub deadline: Option<Instant>,
+}
+
+impl TimeBudget {
+    /// `None` if there is no limit.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+impl<S> FromRequest<S> for TimeBudget {
+    fn from_request(request: &Request<S>) -> Result<Self, crate::Error> {
+        Ok(TimeBudget {
+            deadline: request.config.deadline,
+        })
+    }
+}
+
+/// Why an error handler was called, see [`Graph::add_error_edge`](crate::Graph::add_error_edge).
+#[derive(Debug, Clone)]
+pub struct NodeFailure {
+    /// The node that failed.
+    pub node_key: NodeKey,
+    pub error: Arc<crate::Error>,
+}
+
+impl<S> FromRequest<S> for NodeFailure {
+    fn from_request(request: &Request<S>) -> Result<Self, crate::Error> {
+        request
+            .failure
+            .clone()
+            .ok_or_else(|| crate::Error::ExtractError {
+                extractor: "NodeFailure",
+                reason: "the node was not reached through an error edge".to_string(),
+            })
+    }
+}
+
+/// The input of a node started by a [`Dispatch`](crate::edge::Dispatch), deserialized into `T`.
+#[derive(Debug, Clone)]
+pub struct Input<T>(pub T);
+
+impl<S, T> FromRequest<S> for Input<T>
+where
+    T: DeserializeOwned,
+{
+    fn from_request(request: &Request<S>) -> Result<Self, crate::Error> {
+        let input = request
+            .input
+            .clone()
+            .ok_or_else(|| crate::Error::ExtractError {
+                extractor: "Input",
+                reason: "the node was not dispatched with an input".to_string(),
+            })?;
+        Ok(Input(serde_json::from_value(input)?))
+    }
+}
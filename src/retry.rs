@@ -0,0 +1,117 @@
+use std::{
+    fmt::Debug,
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{Error, NodeError, interrupt::Interrupt};
+
+/// When and how often a failed node is run again, set through
+/// [`NodeOptions::with_retry`](crate::node::NodeOptions::with_retry).
+///
+/// Every attempt runs on its own fork of the state, only the writes of the successful attempt
+/// are applied.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: usize,
+    /// Wait before the second attempt.
+    pub initial_interval: Duration,
+    /// Multiplier of the wait after each attempt.
+    pub backoff_factor: f64,
+    pub max_interval: Duration,
+    /// Randomize each wait between half and the full interval.
+    pub jitter: bool,
+    retry_on: Arc<dyn Fn(&NodeError) -> bool + Send + Sync>,
+}
+
+impl Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_interval", &self.initial_interval)
+            .field("backoff_factor", &self.backoff_factor)
+            .field("max_interval", &self.max_interval)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_interval: Duration::from_millis(500),
+            backoff_factor: 2.0,
+            max_interval: Duration::from_secs(128),
+            jitter: true,
+            retry_on: Arc::new(|_| true),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: usize) -> Self {
+        RetryPolicy {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+    pub fn with_backoff(
+        mut self,
+        initial_interval: Duration,
+        backoff_factor: f64,
+        max_interval: Duration,
+    ) -> Self {
+        self.initial_interval = initial_interval;
+        self.backoff_factor = backoff_factor;
+        self.max_interval = max_interval;
+        self
+    }
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+    /// Only retry the node errors matching `predicate`, by default every node error is retried.
+    pub fn retry_on<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&NodeError) -> bool + Send + Sync + 'static,
+    {
+        self.retry_on = Arc::new(predicate);
+        self
+    }
+    /// Whether `error` of the given attempt (starting at 1) should be retried.
+    pub fn should_retry(&self, attempt: usize, error: &Error) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        match error {
+            // an interrupt waits for a human, retrying won't help
+            Error::NodeExecutionError(error) if error.is::<Interrupt>() => false,
+            Error::NodeExecutionError(error) => (self.retry_on)(error),
+            _ => false,
+        }
+    }
+    /// The wait after the given failed attempt (starting at 1).
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let seconds = (self.initial_interval.as_secs_f64() * self.backoff_factor.powi(exponent))
+            .min(self.max_interval.as_secs_f64())
+            .max(0.0);
+        let interval = Duration::from_secs_f64(seconds);
+        if self.jitter {
+            interval.mul_f64(0.5 + random_fraction() / 2.0)
+        } else {
+            interval
+        }
+    }
+}
+
+/// A random number in `[0, 1)`, good enough to spread retries.
+fn random_fraction() -> f64 {
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
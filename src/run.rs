@@ -0,0 +1,1250 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::channel::mpsc::UnboundedSender;
+use tokio::sync::Semaphore;
+
+use crate::{
+    Error, Graph, GraphError, JsonObject, JsonValue, Response,
+    checkpoint::{Checkpoint, CheckpointSource, CheckpointWriter, PausedSubgraph},
+    edge::Dispatch,
+    interrupt::{Interrupt, InterruptKind, Interrupted},
+    join::{Join, JoinTracker},
+    node::{Node, NodeKey, NodeOptions},
+    request::{NodeFailure, Request},
+    state::{State, StatePatch, StateWrite},
+    stream::{RunEvent, StreamMode},
+    typed::json::JsonValueView,
+};
+
+/// How [`Graph::run`] schedules the nodes of a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Start every successor as soon as its predecessor completes. A node reads the state as it
+    /// was when the node was scheduled, its writes are committed to the shared state as soon as
+    /// it succeeds.
+    #[default]
+    Async,
+    /// Run all triggered nodes in numbered supersteps. Writes are held back until every node of
+    /// the step has completed, then merged in node order, and the edges of the step are resolved
+    /// against the merged state to build the next frontier.
+    Superstep,
+}
+
+/// Counts node executions (or supersteps) of a run against the recursion limit.
+struct StepCounter {
+    limit: Option<usize>,
+    count: usize,
+    history: VecDeque<NodeKey>,
+}
+
+impl StepCounter {
+    const HISTORY_LEN: usize = 16;
+    fn new(limit: Option<usize>) -> Self {
+        StepCounter {
+            limit,
+            count: 0,
+            history: VecDeque::with_capacity(Self::HISTORY_LEN),
+        }
+    }
+    /// Count one more step that runs `nodes`, fails if the limit is already reached.
+    fn step<'a>(&mut self, nodes: impl IntoIterator<Item = &'a NodeKey>) -> Result<(), GraphError> {
+        let mut nodes = nodes.into_iter().peekable();
+        if let (Some(limit), Some(node)) = (self.limit, nodes.peek()) {
+            if self.count >= limit {
+                return Err(GraphError::RecursionLimitExceeded {
+                    limit,
+                    node: (*node).clone(),
+                    history: self.history.iter().cloned().collect(),
+                });
+            }
+        }
+        self.count += 1;
+        for node in nodes {
+            if self.history.len() == Self::HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back(node.clone());
+        }
+        Ok(())
+    }
+}
+
+/// One execution of a node within a run.
+#[derive(Debug, Clone)]
+pub struct NodeExecution {
+    pub node: NodeKey,
+    /// Offset from the start of the run.
+    pub started: Duration,
+    pub elapsed: Duration,
+}
+
+/// An edge taken during a run.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub from: NodeKey,
+    pub to: NodeKey,
+    /// Description of the edge that produced this transition.
+    pub edge: String,
+}
+
+/// Collects the summary of a run while it executes, and forwards it to a stream if there is one.
+pub(crate) struct RunRecorder {
+    started: Instant,
+    executions: Vec<NodeExecution>,
+    path: Vec<Transition>,
+    patches: Vec<StatePatch>,
+    events: Option<(UnboundedSender<RunEvent>, StreamMode)>,
+}
+
+impl RunRecorder {
+    pub(crate) fn new() -> Self {
+        RunRecorder {
+            started: Instant::now(),
+            executions: Vec::new(),
+            path: Vec::new(),
+            patches: Vec::new(),
+            events: None,
+        }
+    }
+    pub(crate) fn with_events(
+        mut self,
+        sender: UnboundedSender<RunEvent>,
+        mode: StreamMode,
+    ) -> Self {
+        self.events = Some((sender, mode));
+        self
+    }
+    fn emit(&self, event: impl FnOnce() -> RunEvent) {
+        if let Some((sender, _)) = &self.events {
+            // the receiver may be gone, the run goes on regardless
+            let _ = sender.unbounded_send(event());
+        }
+    }
+    fn stream_mode(&self) -> Option<StreamMode> {
+        self.events.as_ref().map(|(_, mode)| *mode)
+    }
+    /// Whether the writes committed by each node are reported.
+    fn tracks_writes(&self) -> bool {
+        self.stream_mode() == Some(StreamMode::Updates)
+    }
+    /// Where the tasks report that their node started, once they hold their permits.
+    fn events(&self) -> Option<UnboundedSender<RunEvent>> {
+        self.events.as_ref().map(|(sender, _)| sender.clone())
+    }
+    fn executed(&mut self, task: &TaskCompleted) {
+        if task.node_key == NodeKey::Start {
+            return;
+        }
+        self.executions.push(NodeExecution {
+            node: task.node_key.clone(),
+            started: task.started.saturating_duration_since(self.started),
+            elapsed: task.elapsed,
+        });
+        self.emit(|| RunEvent::NodeFinished {
+            node: task.node_key.clone(),
+            elapsed: task.elapsed,
+        });
+        if task.result.is_ok() && self.tracks_writes() {
+            self.emit(|| RunEvent::Updates {
+                node: task.node_key.clone(),
+                writes: task.writes.clone(),
+            });
+        }
+    }
+    fn transition(&mut self, transition: Transition) {
+        self.emit(|| RunEvent::Transition(transition.clone()));
+        self.path.push(transition);
+    }
+    /// Collect the patches committed to the state so far.
+    fn patched(&mut self, state: &State) {
+        let patches = state.take_patches();
+        if self.stream_mode() == Some(StreamMode::Patches) {
+            for patch in &patches {
+                self.emit(|| RunEvent::Patch(patch.clone()));
+            }
+        }
+        self.patches.extend(patches);
+    }
+    /// Report the whole state after it changed.
+    async fn values(&self, state: &State) {
+        if self.stream_mode() == Some(StreamMode::Values) {
+            let state = state.snapshot().await;
+            self.emit(|| RunEvent::Values { state });
+        }
+    }
+    async fn finish<S>(
+        mut self,
+        request: &Request<S>,
+        interrupted: Option<Interrupted>,
+    ) -> Response {
+        self.patched(&request.state);
+        Response {
+            state: request.state.fetch_view(JsonValueView).await,
+            executions: self.executions,
+            path: self.path,
+            patches: self.patches,
+            interrupted,
+        }
+    }
+}
+
+/// Where a run continues from a checkpoint.
+struct Restored {
+    pending: Vec<Invocation>,
+    /// Nodes that don't stop at their interrupt before, because the run resumes from it.
+    passed: HashSet<NodeKey>,
+}
+
+/// Where a run stops, checkpointed once the running nodes are done.
+struct Pause {
+    node: NodeKey,
+    kind: InterruptKind,
+    payload: Option<JsonValue>,
+    /// The invocation the run stopped at, it gets the resume value.
+    at: Option<Dispatch>,
+}
+
+impl Pause {
+    async fn checkpoint(
+        self,
+        checkpoints: &mut CheckpointWriter,
+        state: &State,
+        pending: Vec<Dispatch>,
+        subgraphs: Vec<PausedSubgraph>,
+    ) -> Result<Interrupted, Error> {
+        let checkpoint = checkpoints
+            .checkpoint(
+                state,
+                pending,
+                CheckpointSource::Interrupt(self.kind),
+                self.at,
+                subgraphs,
+            )
+            .await?;
+        Ok(Interrupted {
+            node: self.node,
+            kind: self.kind,
+            payload: self.payload,
+            checkpoint,
+        })
+    }
+}
+
+/// A node to run, with what only this invocation of it gets.
+#[derive(Debug, Clone)]
+struct Invocation {
+    node_key: NodeKey,
+    input: Option<JsonValue>,
+    failure: Option<NodeFailure>,
+    /// The resume value, for the invocation a resumed run was interrupted at.
+    resume: Option<JsonValue>,
+    /// Where the subgraph run by the invocation paused, when a run is resumed.
+    subgraph: Option<Box<Checkpoint>>,
+}
+
+impl Invocation {
+    fn new(node_key: NodeKey) -> Self {
+        Invocation {
+            node_key,
+            input: None,
+            failure: None,
+            resume: None,
+            subgraph: None,
+        }
+    }
+    fn request<S: Clone>(&self, request: &Request<S>) -> Request<S> {
+        let mut request = Request {
+            input: self.input.clone(),
+            failure: self.failure.clone(),
+            goto: Default::default(),
+            subgraph: self.subgraph.clone(),
+            ..request.clone()
+        };
+        if self.resume.is_some() {
+            request.config.resume = self.resume.clone();
+        }
+        request
+    }
+    fn dispatch(&self) -> Dispatch {
+        Dispatch {
+            node: self.node_key.clone(),
+            input: self.input.clone(),
+        }
+    }
+}
+
+impl From<Dispatch> for Invocation {
+    fn from(dispatch: Dispatch) -> Self {
+        Invocation {
+            input: dispatch.input,
+            ..Invocation::new(dispatch.node)
+        }
+    }
+}
+
+struct TaskCompleted {
+    result: Result<(), Error>,
+    node_key: NodeKey,
+    /// Input of the invocation, if it was dispatched with one.
+    input: Option<JsonValue>,
+    /// Where the [`Command`](crate::node::Command) returned by the node goes.
+    goto: Option<Vec<NodeKey>>,
+    /// When the node was scheduled and the state it reads was taken.
+    scheduled: Instant,
+    started: Instant,
+    elapsed: Duration,
+    /// Writes the node committed, empty if it failed.
+    writes: Vec<StateWrite>,
+}
+
+impl TaskCompleted {
+    #[allow(clippy::too_many_arguments)]
+    async fn run<S>(
+        node: Arc<dyn Node<S>>,
+        node_key: NodeKey,
+        request: Request<S>,
+        base: JsonObject,
+        scheduled: Instant,
+        options: NodeOptions,
+        semaphores: Vec<Arc<Semaphore>>,
+        events: Option<UnboundedSender<RunEvent>>,
+    ) -> Self
+    where
+        S: Clone,
+    {
+        let mut permits = Vec::with_capacity(semaphores.len());
+        for semaphore in semaphores {
+            permits.push(
+                semaphore
+                    .acquire_owned()
+                    .await
+                    .expect("semaphores are never closed"),
+            );
+        }
+        if let Some(events) = events {
+            // the receiver may be gone, the run goes on regardless
+            let _ = events.unbounded_send(RunEvent::NodeStarted {
+                node: node_key.clone(),
+            });
+        }
+        let state = request.state.clone();
+        let input = request.input.clone();
+        let goto = request.goto.clone();
+        let started = Instant::now();
+        let mut writes = Vec::new();
+        let mut attempt = 1;
+        let base = Arc::new(base);
+        let result = loop {
+            // each attempt reads the state as it was when the node was scheduled and writes to
+            // its own transaction view, committed in one go if the node succeeds, so neither
+            // other branches nor a failed attempt leave a trace
+            let attempt_state = state.fork_from(JsonObject::clone(&base));
+            let attempt_request = request
+                .with_state(attempt_state.clone())
+                .with_snapshot(base.clone());
+            let error = match Self::call(node.clone(), &node_key, attempt_request, &options).await {
+                Ok(()) => {
+                    writes = attempt_state.take_writes();
+                    state.made_by(&node_key).apply_writes(writes.clone()).await;
+                    break Ok(());
+                }
+                // a subgraph continues from where it paused, what it wrote until then stays
+                Err(error)
+                    if Interrupt::from_error(&error)
+                        .is_some_and(|interrupt| interrupt.subgraph.is_some()) =>
+                {
+                    writes = attempt_state.take_writes();
+                    state.made_by(&node_key).apply_writes(writes.clone()).await;
+                    break Err(error);
+                }
+                Err(error) => error,
+            };
+            match &options.retry {
+                Some(retry)
+                    if retry.should_retry(attempt, &error)
+                        && !request.config.cancellation.is_cancelled() =>
+                {
+                    let backoff = retry.backoff(attempt);
+                    tracing::warn!(%node_key, attempt, ?backoff, %error, "Retrying node");
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                _ => break Err(error),
+            }
+        };
+        drop(permits);
+        TaskCompleted {
+            result,
+            node_key,
+            input,
+            goto: goto.take(),
+            scheduled,
+            started,
+            elapsed: started.elapsed(),
+            writes,
+        }
+    }
+    /// Call the node once, within its timeout and the run deadline.
+    async fn call<S>(
+        node: Arc<dyn Node<S>>,
+        node_key: &NodeKey,
+        mut request: Request<S>,
+        options: &NodeOptions,
+    ) -> Result<(), Error> {
+        let node_deadline = options
+            .timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        let deadline = match (request.config.deadline, node_deadline) {
+            (Some(run_deadline), Some(node_deadline)) => Some(run_deadline.min(node_deadline)),
+            (run_deadline, node_deadline) => run_deadline.or(node_deadline),
+        };
+        request.config.deadline = deadline;
+        let fut = node.call(request);
+        match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, fut).await {
+                Ok(result) => result,
+                Err(_) => match options.timeout {
+                    Some(timeout) if node_deadline == Some(deadline) => Err(Error::NodeTimeout {
+                        node_key: node_key.clone(),
+                        timeout,
+                    }),
+                    _ => Err(Error::DeadlineExceeded {
+                        node_key: node_key.clone(),
+                    }),
+                },
+            },
+            None => fut.await,
+        }
+    }
+}
+
+impl<S> Graph<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn get_node(&self, node_key: &NodeKey) -> Result<Arc<dyn Node<S>>, GraphError> {
+        self.nodes
+            .get(node_key)
+            .cloned()
+            .ok_or_else(|| GraphError::UndefinedNode(node_key.clone()))
+    }
+    fn get_node_options(&self, node_key: &NodeKey) -> NodeOptions {
+        self.node_options.get(node_key).cloned().unwrap_or_default()
+    }
+    /// The semaphores a node takes a permit of before it starts: the concurrency limit of the
+    /// run first, then its resources by name, so every node acquires them in the same order.
+    fn get_semaphores(
+        &self,
+        node_key: &NodeKey,
+        options: &NodeOptions,
+        limit: Option<&Arc<Semaphore>>,
+    ) -> Result<Vec<Arc<Semaphore>>, GraphError> {
+        let mut semaphores: Vec<_> = limit.into_iter().cloned().collect();
+        for resource in &options.resources {
+            let semaphore =
+                self.resources
+                    .get(resource)
+                    .ok_or_else(|| GraphError::UndefinedResource {
+                        node: node_key.clone(),
+                        resource: resource.clone(),
+                    })?;
+            semaphores.push(semaphore.clone());
+        }
+        Ok(semaphores)
+    }
+    fn concurrency_limit(&self, request: &Request<S>) -> Option<Arc<Semaphore>> {
+        request
+            .config
+            .max_concurrency
+            .or(self.max_concurrency)
+            .map(|limit| Arc::new(Semaphore::new(limit.max(1))))
+    }
+    /// Resolve all out edges of `node_key` in edge order, and record the transitions.
+    async fn resolve_next(
+        &self,
+        node_key: &NodeKey,
+        request: &Request<S>,
+        recorder: &mut RunRecorder,
+    ) -> Result<Vec<Dispatch>, Error> {
+        let edges = self
+            .edges
+            .get(node_key)
+            .filter(|e| !e.is_empty())
+            .ok_or_else(|| GraphError::MissingOutEdge(node_key.clone()))?;
+        // the edges route on the state the node left
+        let request = request.with_snapshot(Arc::new(request.state.snapshot().await));
+        let mut next = Vec::new();
+        for e in edges {
+            let dispatches =
+                e.dispatch(&request)
+                    .await
+                    .map_err(|e| Error::ResolveNextNodesError {
+                        error: Box::new(e),
+                        node_key: node_key.clone(),
+                    })?;
+            for dispatch in dispatches {
+                recorder.transition(Transition {
+                    from: node_key.clone(),
+                    to: dispatch.node.clone(),
+                    edge: e.description(),
+                });
+                next.push(dispatch);
+            }
+        }
+        Ok(next)
+    }
+    /// Run with the scheduler selected by [`Graph::mode`], from the start or from `resume`.
+    pub(crate) async fn execute(
+        self: Arc<Self>,
+        request: Request<S>,
+        recorder: RunRecorder,
+        resume: Option<Checkpoint>,
+    ) -> Result<Response, Error> {
+        // a subgraph keeps the reducers of its parent, the keys they reduce stay hidden from its
+        // nodes and the parent reduces what the subgraph writes
+        let mut reducers = request.state.reducers().clone();
+        reducers.extend(&self.reducers);
+        let mut request = request.with_state(request.state.with_reducers(reducers).recording());
+        let checkpoints = CheckpointWriter::new(&self, &request, resume.as_ref());
+        let restored = match resume {
+            Some(checkpoint) => {
+                request.state.replace(checkpoint.state).await;
+                let passed = match checkpoint.metadata.source {
+                    CheckpointSource::Interrupt(InterruptKind::Before) => checkpoint
+                        .frontier
+                        .iter()
+                        .chain(checkpoint.dispatches.iter().map(|dispatch| &dispatch.node))
+                        .cloned()
+                        .collect(),
+                    _ => HashSet::new(),
+                };
+                // only the interrupted invocation gets the resume value, a node interrupting later
+                // in the run pauses as it should
+                let mut resume = request.config.resume.take();
+                let mut subgraphs = checkpoint.subgraphs;
+                let pending = checkpoint
+                    .frontier
+                    .into_iter()
+                    .map(Invocation::new)
+                    .chain(checkpoint.dispatches.into_iter().map(Invocation::from))
+                    .map(|mut invocation| {
+                        if checkpoint.interrupted.as_ref() == Some(&invocation.dispatch()) {
+                            invocation.resume = resume.take();
+                        }
+                        // a paused subgraph continues where it stopped instead of starting over
+                        if let Some(index) = subgraphs
+                            .iter()
+                            .position(|paused| paused.invocation == invocation.dispatch())
+                        {
+                            invocation.subgraph =
+                                Some(Box::new(subgraphs.swap_remove(index).checkpoint));
+                        }
+                        invocation
+                    })
+                    .collect();
+                Some(Restored { pending, passed })
+            }
+            None => None,
+        };
+        match self.mode {
+            ExecutionMode::Async => {
+                self.run_async(request, recorder, checkpoints, restored)
+                    .await
+            }
+            ExecutionMode::Superstep => {
+                self.run_superstep(request, recorder, checkpoints, restored)
+                    .await
+            }
+        }
+    }
+    /// Park the restored invocations of nodes with a join, returns the invocations to run.
+    fn restore_pending(
+        &self,
+        joins: &mut JoinTracker,
+        pending: Vec<Invocation>,
+    ) -> Vec<Invocation> {
+        pending
+            .into_iter()
+            .filter(
+                |invocation| match (&invocation.input, self.joins.get(&invocation.node_key)) {
+                    (Some(_), _) | (None, None | Some(Join::Any)) => true,
+                    (None, Some(_)) => {
+                        joins.wait(invocation.node_key.clone());
+                        false
+                    }
+                },
+            )
+            .collect()
+    }
+    async fn run_async(
+        self: Arc<Self>,
+        request: Request<S>,
+        mut recorder: RunRecorder,
+        mut checkpoints: CheckpointWriter,
+        restored: Option<Restored>,
+    ) -> Result<Response, Error> {
+        let mut task_set = tokio::task::JoinSet::new();
+        // the inputs of the running invocations of each node
+        let mut running = HashMap::<NodeKey, Vec<Option<JsonValue>>>::new();
+        let mut joins = JoinTracker::default();
+        let mut counter = StepCounter::new(request.config.recursion_limit.or(self.recursion_limit));
+        let cancellation = request.config.cancellation.clone();
+        let limit = self.concurrency_limit(&request);
+        // nodes that were running when the run got cancelled
+        let mut cancelled: Option<HashSet<NodeKey>> = None;
+        // nodes to start, and whether something completed since the last scheduling
+        let mut ready: Vec<Invocation> = Vec::new();
+        let mut schedule = false;
+        let mut source = None;
+        // once interrupted, nodes are held back instead of started
+        let mut interrupted: Option<Pause> = None;
+        let mut subgraphs: Vec<PausedSubgraph> = Vec::new();
+        let mut held: Vec<Invocation> = Vec::new();
+        let mut passed = HashSet::new();
+        // the writes of completed nodes and when they completed, while other nodes still run
+        let mut concurrent_writes: Vec<(NodeKey, Instant, Vec<StateWrite>)> = Vec::new();
+        match restored {
+            Some(restored) => {
+                ready = self.restore_pending(&mut joins, restored.pending);
+                passed = restored.passed;
+                schedule = true;
+            }
+            None => {
+                task_set.spawn(futures::future::ready(
+                    // start trigger task
+                    TaskCompleted {
+                        result: Ok(()),
+                        node_key: NodeKey::Start,
+                        input: None,
+                        goto: None,
+                        scheduled: Instant::now(),
+                        started: Instant::now(),
+                        elapsed: Duration::ZERO,
+                        writes: Vec::new(),
+                    },
+                ));
+            }
+        }
+        loop {
+            if schedule {
+                schedule = false;
+                loop {
+                    for invocation in ready.drain(..) {
+                        let to_node_key = invocation.node_key.clone();
+                        if interrupted.is_none()
+                            && self.interrupt_before.contains(&to_node_key)
+                            && !passed.remove(&to_node_key)
+                        {
+                            tracing::info!(%to_node_key, "Interrupted before node");
+                            interrupted = Some(Pause {
+                                node: to_node_key.clone(),
+                                kind: InterruptKind::Before,
+                                payload: None,
+                                at: Some(invocation.dispatch()),
+                            });
+                        }
+                        if interrupted.is_some() {
+                            held.push(invocation);
+                            continue;
+                        }
+                        counter.step([&to_node_key])?;
+                        if request.config.deadline_passed() {
+                            return Err(Error::DeadlineExceeded {
+                                node_key: to_node_key,
+                            });
+                        }
+                        let node = self.get_node(&to_node_key)?;
+                        running
+                            .entry(to_node_key.clone())
+                            .or_default()
+                            .push(invocation.input.clone());
+                        let options = self.get_node_options(&to_node_key);
+                        let semaphores =
+                            self.get_semaphores(&to_node_key, &options, limit.as_ref())?;
+                        let scheduled = Instant::now();
+                        let base = request.state.snapshot().await;
+                        task_set.spawn(TaskCompleted::run(
+                            node,
+                            to_node_key,
+                            invocation.request(&request),
+                            base,
+                            scheduled,
+                            options,
+                            semaphores,
+                            recorder.events(),
+                        ));
+                    }
+                    ready = joins
+                        .take_ready(
+                            &self,
+                            running
+                                .keys()
+                                .chain(held.iter().map(|invocation| &invocation.node_key)),
+                        )
+                        .into_iter()
+                        .map(Invocation::new)
+                        .collect();
+                    if ready.is_empty() && running.is_empty() && interrupted.is_none() {
+                        // nothing left that could arrive, release the remaining joins
+                        ready = joins.take_all().into_iter().map(Invocation::new).collect();
+                    }
+                    if ready.is_empty() {
+                        break;
+                    }
+                }
+                if let Some(source) = source.take().filter(|_| interrupted.is_none()) {
+                    let pending = Self::pending(
+                        running.iter().flat_map(|(node_key, inputs)| {
+                            inputs.iter().map(|input| Dispatch {
+                                node: node_key.clone(),
+                                input: input.clone(),
+                            })
+                        }),
+                        &joins,
+                    );
+                    checkpoints.save(&request.state, pending, source).await?;
+                }
+            }
+            enum Event {
+                TaskCompleted(TaskCompleted),
+                Cancelled,
+            }
+            let event = tokio::select! {
+                biased;
+                _ = cancellation.cancelled(), if cancelled.is_none() && !task_set.is_empty() => {
+                    Event::Cancelled
+                }
+                result = task_set.join_next(), if !task_set.is_empty() => {
+                    Event::TaskCompleted(result.expect("not empty set")?)
+                    // Handle the result of the completed task
+                }
+                else => {
+                    // All tasks completed
+                    break;
+                }
+            };
+            match event {
+                Event::Cancelled => {
+                    tracing::info!(?running, "Run cancelled, waiting for running nodes");
+                    cancelled = Some(running.keys().cloned().collect());
+                }
+                Event::TaskCompleted(task) => {
+                    recorder.executed(&task);
+                    if self.detect_conflicts && !task.writes.is_empty() {
+                        for (node_key, completed, writes) in &concurrent_writes {
+                            if *completed > task.scheduled {
+                                self.check_conflict(
+                                    node_key,
+                                    writes,
+                                    &task.node_key,
+                                    &task.writes,
+                                )?;
+                            }
+                        }
+                        concurrent_writes.push((
+                            task.node_key.clone(),
+                            task.started + task.elapsed,
+                            task.writes.clone(),
+                        ));
+                    }
+                    let TaskCompleted {
+                        result,
+                        node_key,
+                        input,
+                        goto,
+                        ..
+                    } = task;
+                    if let Some(inputs) = running.get_mut(&node_key) {
+                        if let Some(index) = inputs.iter().position(|running| *running == input) {
+                            inputs.swap_remove(index);
+                        }
+                        if inputs.is_empty() {
+                            running.remove(&node_key);
+                        }
+                    }
+                    if running.is_empty() {
+                        concurrent_writes.clear();
+                    }
+                    if cancelled.is_some() {
+                        // let the running nodes finish, but don't schedule anything new
+                        continue;
+                    }
+                    recorder.patched(&request.state);
+                    if node_key != NodeKey::Start {
+                        recorder.values(&request.state).await;
+                    }
+                    source = Some(if node_key == NodeKey::Start {
+                        CheckpointSource::Input
+                    } else {
+                        CheckpointSource::Loop
+                    });
+                    schedule = true;
+                    match result {
+                        Ok(()) => {
+                            tracing::info!(%node_key, "Node completed");
+                            for dispatch in self
+                                .resolve_next(&node_key, &request.with_goto(goto), &mut recorder)
+                                .await?
+                            {
+                                if dispatch.node == NodeKey::End {
+                                    continue;
+                                }
+                                if dispatch.input.is_some()
+                                    || joins.arrive(&self, &node_key, &dispatch.node)
+                                {
+                                    ready.push(Invocation::from(dispatch));
+                                }
+                            }
+                            if interrupted.is_none() && self.interrupt_after.contains(&node_key) {
+                                tracing::info!(%node_key, "Interrupted after node");
+                                interrupted = Some(Pause {
+                                    node: node_key,
+                                    kind: InterruptKind::After,
+                                    payload: None,
+                                    at: None,
+                                });
+                            }
+                        }
+                        Err(error) => {
+                            if let Some(interrupt) = Interrupt::from_error(&error) {
+                                tracing::info!(%node_key, "Interrupted by node");
+                                if interrupted.is_none() {
+                                    interrupted = Some(Pause {
+                                        node: node_key.clone(),
+                                        kind: InterruptKind::Node,
+                                        payload: Some(interrupt.payload.clone()),
+                                        at: Some(Dispatch {
+                                            node: node_key.clone(),
+                                            input: input.clone(),
+                                        }),
+                                    });
+                                }
+                                if let Some(checkpoint) = &interrupt.subgraph {
+                                    subgraphs.push(PausedSubgraph {
+                                        invocation: Dispatch {
+                                            node: node_key.clone(),
+                                            input: input.clone(),
+                                        },
+                                        checkpoint: Checkpoint::clone(checkpoint),
+                                    });
+                                }
+                                // the node runs again on resume
+                                held.push(Invocation::from(Dispatch {
+                                    node: node_key,
+                                    input,
+                                }));
+                            } else if let Some(handler) =
+                                self.route_failure(node_key, error, &mut recorder)?
+                            {
+                                ready.push(handler);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(in_flight) = cancelled {
+            return Err(Error::Cancelled { in_flight });
+        }
+        let interrupted = match interrupted {
+            Some(pause) => {
+                let pending = Self::pending(held.iter().map(Invocation::dispatch), &joins);
+                Some(
+                    pause
+                        .checkpoint(&mut checkpoints, &request.state, pending, subgraphs)
+                        .await?,
+                )
+            }
+            None => None,
+        };
+        Ok(recorder.finish(&request, interrupted).await)
+    }
+    async fn run_superstep(
+        self: Arc<Self>,
+        request: Request<S>,
+        mut recorder: RunRecorder,
+        mut checkpoints: CheckpointWriter,
+        restored: Option<Restored>,
+    ) -> Result<Response, Error> {
+        let mut joins = JoinTracker::default();
+        let mut counter = StepCounter::new(request.config.recursion_limit.or(self.recursion_limit));
+        let cancellation = request.config.cancellation.clone();
+        let limit = self.concurrency_limit(&request);
+        let mut passed = HashSet::new();
+        // invocations of the next step besides the frontier: error handlers and dispatches with
+        // an input
+        let mut extra: Vec<Invocation> = Vec::new();
+        let mut frontier = match restored {
+            Some(restored) => {
+                passed = restored.passed;
+                let mut frontier = BTreeSet::new();
+                for invocation in self.restore_pending(&mut joins, restored.pending) {
+                    if invocation.input.is_some() {
+                        extra.push(invocation);
+                    } else {
+                        frontier.insert(invocation.node_key);
+                    }
+                }
+                self.release_joins(&mut joins, &mut frontier, &extra);
+                frontier
+            }
+            None => {
+                let frontier = self
+                    .next_frontier(
+                        &mut joins,
+                        &request,
+                        &mut recorder,
+                        [(NodeKey::Start, None)],
+                        &mut extra,
+                    )
+                    .await?;
+                let pending = Self::pending(
+                    frontier
+                        .iter()
+                        .cloned()
+                        .map(Dispatch::to)
+                        .chain(extra.iter().map(Invocation::dispatch)),
+                    &joins,
+                );
+                checkpoints
+                    .save(&request.state, pending, CheckpointSource::Input)
+                    .await?;
+                frontier
+            }
+        };
+        let mut interrupted = None;
+        let mut step = 0usize;
+        while !frontier.is_empty() || !extra.is_empty() {
+            if cancellation.is_cancelled() {
+                return Err(Error::Cancelled {
+                    in_flight: HashSet::new(),
+                });
+            }
+            let invocations: Vec<Invocation> = frontier
+                .into_iter()
+                .map(Invocation::new)
+                .chain(extra.drain(..))
+                .collect();
+            let before = invocations
+                .iter()
+                .find(|invocation| {
+                    self.interrupt_before.contains(&invocation.node_key)
+                        && !passed.contains(&invocation.node_key)
+                })
+                .map(Invocation::dispatch);
+            passed.clear();
+            if let Some(at) = before {
+                let node = at.node.clone();
+                // the whole step waits for the resume
+                tracing::info!(%node, "Interrupted before node");
+                let pending = Self::pending(invocations.iter().map(Invocation::dispatch), &joins);
+                let pause = Pause {
+                    node,
+                    kind: InterruptKind::Before,
+                    payload: None,
+                    at: Some(at),
+                };
+                interrupted = Some(
+                    pause
+                        .checkpoint(&mut checkpoints, &request.state, pending, Vec::new())
+                        .await?,
+                );
+                break;
+            }
+            counter.step(invocations.iter().map(|invocation| &invocation.node_key))?;
+            if request.config.deadline_passed() {
+                return Err(Error::DeadlineExceeded {
+                    node_key: invocations[0].node_key.clone(),
+                });
+            }
+            tracing::info!(step, ?invocations, "Superstep started");
+            let base = request.state.snapshot().await;
+            let mut task_set = tokio::task::JoinSet::new();
+            for (index, invocation) in invocations.iter().enumerate() {
+                let node_key = &invocation.node_key;
+                let node = self.get_node(node_key)?;
+                let node_request =
+                    invocation.request(&request.with_state(request.state.fork_from(base.clone())));
+                let options = self.get_node_options(node_key);
+                let semaphores = self.get_semaphores(node_key, &options, limit.as_ref())?;
+                let task = TaskCompleted::run(
+                    node,
+                    node_key.clone(),
+                    node_request,
+                    base.clone(),
+                    Instant::now(),
+                    options,
+                    semaphores,
+                    recorder.events(),
+                );
+                task_set.spawn(async move { (index, task.await) });
+            }
+            // `Some((writes, goto))` for each invocation that succeeded
+            let mut completed: Vec<Option<(Vec<StateWrite>, Option<Vec<NodeKey>>)>> =
+                vec![None; invocations.len()];
+            let mut finished = vec![false; invocations.len()];
+            let mut cancelled: Option<HashSet<NodeKey>> = None;
+            // invocations that raised an interrupt, they run again on resume
+            let mut raised: Vec<(Invocation, Interrupt)> = Vec::new();
+            // what the interrupted subgraphs wrote before they paused, kept as they continue
+            let mut paused: Vec<Vec<StateWrite>> = vec![Vec::new(); invocations.len()];
+            loop {
+                let result = tokio::select! {
+                    biased;
+                    _ = cancellation.cancelled(), if cancelled.is_none() => {
+                        cancelled = Some(
+                            invocations
+                                .iter()
+                                .zip(&finished)
+                                .filter(|(_, finished)| !**finished)
+                                .map(|(invocation, _)| invocation.node_key.clone())
+                                .collect(),
+                        );
+                        continue;
+                    }
+                    result = task_set.join_next() => match result {
+                        Some(result) => result,
+                        None => break,
+                    },
+                };
+                let (index, task) = result?;
+                finished[index] = true;
+                recorder.executed(&task);
+                if cancelled.is_some() {
+                    continue;
+                }
+                match task.result {
+                    Ok(()) => completed[index] = Some((task.writes, task.goto)),
+                    Err(error) => match Interrupt::from_error(&error) {
+                        Some(interrupt) => {
+                            paused[index] = task.writes;
+                            raised.push((invocations[index].clone(), interrupt.clone()))
+                        }
+                        None => {
+                            extra.extend(self.route_failure(task.node_key, error, &mut recorder)?)
+                        }
+                    },
+                }
+            }
+            if let Some(in_flight) = cancelled {
+                // the writes of an unfinished step are dropped
+                return Err(Error::Cancelled { in_flight });
+            }
+            if self.detect_conflicts {
+                let written: Vec<_> = invocations
+                    .iter()
+                    .zip(&completed)
+                    .filter_map(|(invocation, completed)| {
+                        completed
+                            .as_ref()
+                            .map(|(writes, _)| (&invocation.node_key, writes))
+                    })
+                    .collect();
+                for (index, (first, first_writes)) in written.iter().enumerate() {
+                    for (second, second_writes) in &written[index + 1..] {
+                        self.check_conflict(first, first_writes, second, second_writes)?;
+                    }
+                }
+            }
+            // merge in invocation order, so the result doesn't depend on timing
+            let mut succeeded = Vec::new();
+            for ((invocation, completed), paused) in
+                invocations.into_iter().zip(completed).zip(paused)
+            {
+                match completed {
+                    Some((writes, goto)) => {
+                        request
+                            .state
+                            .made_by(&invocation.node_key)
+                            .apply_writes(writes)
+                            .await;
+                        succeeded.push((invocation.node_key, goto));
+                    }
+                    None if !paused.is_empty() => {
+                        request
+                            .state
+                            .made_by(&invocation.node_key)
+                            .apply_writes(paused)
+                            .await;
+                    }
+                    None => {}
+                }
+            }
+            recorder.patched(&request.state);
+            recorder.values(&request.state).await;
+            let after = succeeded
+                .iter()
+                .map(|(node_key, _)| node_key)
+                .find(|node_key| self.interrupt_after.contains(*node_key))
+                .cloned();
+            frontier = self
+                .next_frontier(&mut joins, &request, &mut recorder, succeeded, &mut extra)
+                .await?;
+            let pending = Self::pending(
+                frontier
+                    .iter()
+                    .cloned()
+                    .map(Dispatch::to)
+                    .chain(extra.iter().map(Invocation::dispatch))
+                    .chain(raised.iter().map(|(invocation, _)| invocation.dispatch())),
+                &joins,
+            );
+            step += 1;
+            let subgraphs: Vec<PausedSubgraph> = raised
+                .iter()
+                .filter_map(|(invocation, interrupt)| {
+                    Some(PausedSubgraph {
+                        invocation: invocation.dispatch(),
+                        checkpoint: Checkpoint::clone(interrupt.subgraph.as_ref()?),
+                    })
+                })
+                .collect();
+            let pause = match (raised.into_iter().next(), after) {
+                (Some((invocation, interrupt)), _) => Some(Pause {
+                    node: invocation.node_key.clone(),
+                    kind: InterruptKind::Node,
+                    payload: Some(interrupt.payload),
+                    at: Some(invocation.dispatch()),
+                }),
+                (None, Some(node)) => Some(Pause {
+                    node,
+                    kind: InterruptKind::After,
+                    payload: None,
+                    at: None,
+                }),
+                (None, None) => None,
+            };
+            if let Some(pause) = pause {
+                tracing::info!(node = %pause.node, kind = ?pause.kind, "Interrupted");
+                interrupted = Some(
+                    pause
+                        .checkpoint(&mut checkpoints, &request.state, pending, subgraphs)
+                        .await?,
+                );
+                break;
+            }
+            checkpoints
+                .save(&request.state, pending, CheckpointSource::Loop)
+                .await?;
+        }
+        Ok(recorder.finish(&request, interrupted).await)
+    }
+    /// Fail if `first` and `second` both wrote a key that has no reducer.
+    fn check_conflict(
+        &self,
+        first: &NodeKey,
+        first_writes: &[StateWrite],
+        second: &NodeKey,
+        second_writes: &[StateWrite],
+    ) -> Result<(), Error> {
+        let conflict = second_writes
+            .iter()
+            .map(StateWrite::key)
+            .filter(|key| self.reducers.get(key).is_none())
+            .find(|key| first_writes.iter().any(|write| write.key() == *key));
+        match conflict {
+            Some(key) => Err(Error::InvalidConcurrentUpdate {
+                key: key.to_string(),
+                first: first.clone(),
+                second: second.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+    /// Route a failure of `node_key` along its error edge, returns the handler to schedule.
+    fn route_failure(
+        &self,
+        node_key: NodeKey,
+        error: Error,
+        recorder: &mut RunRecorder,
+    ) -> Result<Option<Invocation>, Error> {
+        let handler = match (&error, self.error_edges.get(&node_key)) {
+            // the run is out of time, there is nothing a handler could do
+            (Error::DeadlineExceeded { .. }, _) | (_, None) => return Err(error),
+            (_, Some(handler)) => handler.clone(),
+        };
+        tracing::warn!(%node_key, %handler, %error, "Node failed, routing to error handler");
+        recorder.transition(Transition {
+            from: node_key.clone(),
+            to: handler.clone(),
+            edge: format!("Error Edge to NodeKey({handler})"),
+        });
+        if handler == NodeKey::End {
+            return Ok(None);
+        }
+        Ok(Some(Invocation {
+            failure: Some(NodeFailure {
+                node_key,
+                error: Arc::new(error),
+            }),
+            ..Invocation::new(handler)
+        }))
+    }
+    /// Resolve the edges of the `completed` nodes into the frontier of the next superstep,
+    /// dispatches with an input are added to `extra`.
+    async fn next_frontier(
+        &self,
+        joins: &mut JoinTracker,
+        request: &Request<S>,
+        recorder: &mut RunRecorder,
+        completed: impl IntoIterator<Item = (NodeKey, Option<Vec<NodeKey>>)>,
+        extra: &mut Vec<Invocation>,
+    ) -> Result<BTreeSet<NodeKey>, Error> {
+        let mut frontier = BTreeSet::new();
+        for (node_key, goto) in completed {
+            let request = request.with_goto(goto);
+            for dispatch in self.resolve_next(&node_key, &request, recorder).await? {
+                if dispatch.node == NodeKey::End {
+                    continue;
+                }
+                if dispatch.input.is_some() {
+                    extra.push(Invocation::from(dispatch));
+                } else if joins.arrive(self, &node_key, &dispatch.node) {
+                    frontier.insert(dispatch.node);
+                }
+            }
+        }
+        self.release_joins(joins, &mut frontier, extra);
+        Ok(frontier)
+    }
+    /// Add the joining nodes that can no longer wait for anything to `frontier`.
+    fn release_joins(
+        &self,
+        joins: &mut JoinTracker,
+        frontier: &mut BTreeSet<NodeKey>,
+        extra: &[Invocation],
+    ) {
+        loop {
+            let ready = joins.take_ready(
+                self,
+                frontier
+                    .iter()
+                    .chain(extra.iter().map(|invocation| &invocation.node_key)),
+            );
+            if ready.is_empty() {
+                break;
+            }
+            frontier.extend(ready);
+        }
+        if frontier.is_empty() && extra.is_empty() {
+            frontier.extend(joins.take_all());
+        }
+    }
+    /// The invocations pending at a checkpoint: `invocations` and the nodes waiting at their
+    /// join.
+    fn pending(
+        invocations: impl IntoIterator<Item = Dispatch>,
+        joins: &JoinTracker,
+    ) -> Vec<Dispatch> {
+        let mut pending: Vec<Dispatch> = invocations
+            .into_iter()
+            .chain(joins.waiting().cloned().map(Dispatch::to))
+            .collect();
+        pending.sort_by(|a, b| a.node.cmp(&b.node));
+        pending
+    }
+}
@@ -1,29 +1,179 @@
-use std::sync::Arc;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
 
-use modify::{Modification, SendDynModification};
+use modify::Modification;
 use serde::Serialize;
 
-use crate::{JsonValue, request::FromRequest};
+use crate::{JsonObject, JsonValue, node::NodeKey, request::FromRequest};
+
+mod ops;
+pub use ops::{Increment, MergePatch, Push, Remove, Set};
+mod patch;
+use patch::PatchLog;
+pub use patch::StatePatch;
+mod reducer;
+pub use reducer::{Append, ReduceFn, Reducer, Reducers, Sum, Union};
 
 pub trait View<T> {
     type Data;
     fn view(self, target: &T) -> Self::Data;
 }
 
+/// Handle to the shared JSON state of a run.
+///
+/// A handle may keep a journal of the top-level writes made through it, see [`State::tracked`].
+/// Writes to keys with a reducer are combined with the current value, see
+/// [`Graph::add_reducer`](crate::Graph::add_reducer).
+///
+/// The state a node gets is its own transaction view: a snapshot taken when the node was scheduled
+/// with the node's own writes on top, other branches don't show through. The run commits the
+/// writes to the shared state once the node succeeds and drops them if it fails.
 #[derive(Debug, Default, Clone)]
-pub struct State(pub Arc<tokio::sync::RwLock<crate::JsonObject>>);
+pub struct State {
+    object: Arc<tokio::sync::RwLock<crate::JsonObject>>,
+    journal: Option<Arc<Mutex<Vec<StateWrite>>>>,
+    reducers: Reducers,
+    patches: Option<PatchLog>,
+}
 impl State {
     pub async fn apply_modification<M>(&self, modification: M)
     where
         M: Modification<crate::JsonObject>,
     {
-        let mut state = self.0.write().await;
-        modification.modify(&mut state);
+        let mut state = self.object.write().await;
+        if self.journal.is_none() && self.reducers.is_empty() && self.patches.is_none() {
+            modification.modify(&mut state);
+            return;
+        }
+        let writes = self.reducers.writes(&state, modification);
+        self.write(&mut state, writes);
+    }
+    fn write(&self, object: &mut JsonObject, writes: impl IntoIterator<Item = StateWrite>) {
+        // the previous values of the keys written, to record the change as a patch
+        let mut before = self.patches.as_ref().map(|_| BTreeMap::new());
+        for write in writes {
+            if let Some(before) = &mut before {
+                before
+                    .entry(write.key().to_owned())
+                    .or_insert_with(|| object.get(write.key()).cloned());
+            }
+            if let Some(journal) = &self.journal {
+                journal
+                    .lock()
+                    .expect("journal poisoned")
+                    .push(write.clone());
+            }
+            self.reducers.apply(object, write);
+        }
+        if let (Some(patches), Some(before)) = (&self.patches, before) {
+            patches.record(before, object);
+        }
     }
     pub async fn fetch_view<V: View<crate::JsonObject>>(&self, view: V) -> V::Data {
-        let state = self.0.read().await;
+        let state = self.object.read().await;
         view.view(&state)
     }
+    /// A copy of the current state object.
+    pub async fn snapshot(&self) -> JsonObject {
+        self.object.read().await.clone()
+    }
+    /// A handle to the same state that records the writes made through it.
+    pub fn tracked(&self) -> State {
+        State {
+            object: self.object.clone(),
+            journal: Some(Default::default()),
+            ..self.clone()
+        }
+    }
+    /// A handle to the same state that records every change made through it, or a handle made
+    /// from it, as a [`StatePatch`].
+    pub fn recording(&self) -> State {
+        State {
+            patches: Some(PatchLog::default()),
+            ..self.clone()
+        }
+    }
+    /// A handle that tags the patches it records with `node`.
+    pub(crate) fn made_by(&self, node: &NodeKey) -> State {
+        State {
+            patches: self
+                .patches
+                .as_ref()
+                .map(|patches| patches.made_by(node.clone())),
+            ..self.clone()
+        }
+    }
+    /// Drain the patches recorded by a recording handle, in sequence order.
+    pub fn take_patches(&self) -> Vec<StatePatch> {
+        self.patches
+            .as_ref()
+            .map(PatchLog::take)
+            .unwrap_or_default()
+    }
+    /// Apply a recorded patch as it is, bypassing reducers, journal and recording. Applying the
+    /// patches of a state in sequence order onto where it started from rebuilds it.
+    pub async fn apply_patch(&self, patch: &StatePatch) -> Result<(), crate::Error> {
+        let mut state = self.object.write().await;
+        let mut value = JsonValue::Object(state.clone());
+        json_patch::patch(&mut value, &patch.patch.0)?;
+        match value {
+            JsonValue::Object(object) => {
+                *state = object;
+                Ok(())
+            }
+            _ => Err(crate::Error::SerdeError(serde::de::Error::custom(
+                "the patched state is not an object",
+            ))),
+        }
+    }
+    /// A detached, tracked state starting from a copy of this one, writes to it don't affect
+    /// `self`.
+    pub async fn fork(&self) -> State {
+        self.fork_from(self.snapshot().await)
+    }
+    /// Like [`State::fork`], but starting from `object`.
+    pub fn fork_from(&self, object: JsonObject) -> State {
+        State {
+            reducers: self.reducers.clone(),
+            ..State::from_object(object)
+        }
+        .tracked()
+    }
+    pub fn reducers(&self) -> &Reducers {
+        &self.reducers
+    }
+    /// A handle to the same state that combines writes with `reducers`.
+    pub fn with_reducers(&self, reducers: Reducers) -> State {
+        State {
+            reducers,
+            ..self.clone()
+        }
+    }
+    /// Drain the writes recorded by a tracked handle, in the order they were made.
+    pub fn take_writes(&self) -> Vec<StateWrite> {
+        self.journal
+            .as_ref()
+            .map(|journal| std::mem::take(&mut *journal.lock().expect("journal poisoned")))
+            .unwrap_or_default()
+    }
+    pub async fn apply_writes(&self, writes: impl IntoIterator<Item = StateWrite>) {
+        let mut state = self.object.write().await;
+        self.write(&mut state, writes);
+    }
+    /// Replace the whole state object, the journal is left untouched.
+    pub async fn replace(&self, object: JsonObject) {
+        *self.object.write().await = object;
+    }
+    pub fn from_object(object: JsonObject) -> State {
+        State {
+            object: Arc::new(tokio::sync::RwLock::new(object)),
+            journal: None,
+            reducers: Reducers::default(),
+            patches: None,
+        }
+    }
     // pub fn merge(&mut self, other: &State) {
     //     for (k, v) in &other.0 {
     //         self.0.insert(k.clone(), v.clone());
@@ -31,7 +181,7 @@ impl State {
     // }
     pub fn from_json_value(value: JsonValue) -> State {
         match value {
-            JsonValue::Object(map) => State(Arc::new(tokio::sync::RwLock::new(map))),
+            JsonValue::Object(map) => State::from_object(map),
             _ => State::default(),
         }
     }
@@ -57,31 +207,226 @@ impl State {
 //     }
 // }
 
-pub trait IntoStateModification {
-    fn into_state(self) -> Result<SendDynModification<State>, crate::Error>;
+/// A write to a single top-level key of the state.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateWrite {
+    Set { key: String, value: JsonValue },
+    Remove { key: String },
+}
+
+impl StateWrite {
+    pub fn key(&self) -> &str {
+        match self {
+            StateWrite::Set { key, .. } | StateWrite::Remove { key } => key,
+        }
+    }
+    /// The top-level writes that turn `before` into `after`.
+    pub fn diff(before: &JsonObject, after: &JsonObject) -> Vec<StateWrite> {
+        let mut writes = Vec::new();
+        for (key, value) in after {
+            if before.get(key) != Some(value) {
+                writes.push(StateWrite::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        for key in before.keys() {
+            if !after.contains_key(key) {
+                writes.push(StateWrite::Remove { key: key.clone() });
+            }
+        }
+        writes
+    }
+}
+
+impl Modification<JsonObject> for StateWrite {
+    fn modify(self, value: &mut JsonObject) {
+        match self {
+            StateWrite::Set { key, value: new } => {
+                value.insert(key, new);
+            }
+            StateWrite::Remove { key } => {
+                value.remove(&key);
+            }
+        }
+    }
 }
 
-impl<T, E> IntoStateModification for Result<T, E>
+/// A modification of the state object, boxed to be applied later by the run.
+pub struct StateUpdate(Box<dyn FnOnce(&mut JsonObject) + Send>);
+
+impl StateUpdate {
+    pub fn new<M>(modification: M) -> Self
+    where
+        M: Modification<JsonObject> + Send + 'static,
+    {
+        StateUpdate(Box::new(move |object| modification.modify(object)))
+    }
+}
+
+impl Modification<JsonObject> for StateUpdate {
+    fn modify(self, value: &mut JsonObject) {
+        (self.0)(value)
+    }
+}
+
+impl std::fmt::Debug for StateUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateUpdate").finish_non_exhaustive()
+    }
+}
+
+/// What a node function may return for the run to apply to the state once it completes, `None`
+/// leaves the state as it is.
+pub trait IntoStateModification<A> {
+    fn into_state(self) -> Result<Option<StateUpdate>, crate::Error>;
+}
+
+pub enum ByModification {}
+
+impl<M> IntoStateModification<ByModification> for M
 where
-    T: IntoStateModification,
+    M: Modification<JsonObject> + Send + 'static,
+{
+    fn into_state(self) -> Result<Option<StateUpdate>, crate::Error> {
+        Ok(Some(StateUpdate::new(self)))
+    }
+}
+
+pub struct ByResult<A> {
+    _marker: std::marker::PhantomData<A>,
+}
+
+impl<T, E, A> IntoStateModification<ByResult<A>> for Result<T, E>
+where
+    T: IntoStateModification<A>,
     crate::Error: From<E>,
 {
-    fn into_state(self) -> Result<SendDynModification<State>, crate::Error> {
+    fn into_state(self) -> Result<Option<StateUpdate>, crate::Error> {
         self.map_err(|e| e.into()).and_then(|s| s.into_state())
     }
 }
 
-// impl IntoStateModification for State {
-//     fn into_state(self) -> Result<SendDynModification<State>, crate::Error> {
-//         Ok(self)
-//     }
-// }
+pub struct ByOption<A> {
+    _marker: std::marker::PhantomData<A>,
+}
 
+impl<T, A> IntoStateModification<ByOption<A>> for Option<T>
+where
+    T: IntoStateModification<A>,
+{
+    fn into_state(self) -> Result<Option<StateUpdate>, crate::Error> {
+        match self {
+            Some(modification) => modification.into_state(),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The adapters of the elements of a tuple a node may return, a `Result` or an `Option` of a
+/// modification. A tuple of plain modifications is a [`Modification`] itself.
+pub trait TupleElement {}
+
+impl<A> TupleElement for ByResult<A> {}
+impl<A> TupleElement for ByOption<A> {}
+
+pub struct ByTuple<A> {
+    _marker: std::marker::PhantomData<A>,
+}
+
+macro_rules! impl_for_tuple {
+    ($($T: ident $A: ident)*) => {
+        /// The modifications of the elements, applied in order.
+        impl<$($T, $A,)*> IntoStateModification<ByTuple<($($A,)*)>> for ($($T,)*)
+        where
+            $($T: IntoStateModification<$A>, $A: TupleElement,)*
+        {
+            #[allow(non_snake_case)]
+            fn into_state(self) -> Result<Option<StateUpdate>, crate::Error> {
+                let ($($T,)*) = self;
+                let updates = [$($T.into_state()?,)*];
+                if updates.iter().all(Option::is_none) {
+                    return Ok(None);
+                }
+                Ok(Some(StateUpdate(Box::new(move |object| {
+                    for update in updates.into_iter().flatten() {
+                        update.modify(object);
+                    }
+                }))))
+            }
+        }
+    };
+}
+
+impl_for_tuple!(T0 A0 T1 A1);
+impl_for_tuple!(T0 A0 T1 A1 T2 A2);
+impl_for_tuple!(T0 A0 T1 A1 T2 A2 T3 A3);
+impl_for_tuple!(T0 A0 T1 A1 T2 A2 T3 A3 T4 A4);
+impl_for_tuple!(T0 A0 T1 A1 T2 A2 T3 A3 T4 A4 T5 A5);
+impl_for_tuple!(T0 A0 T1 A1 T2 A2 T3 A3 T4 A4 T5 A5 T6 A6);
+impl_for_tuple!(T0 A0 T1 A1 T2 A2 T3 A3 T4 A4 T5 A5 T6 A6 T7 A7);
+
+/// A typed value with the [`Merger`] that combines it with the next one.
 pub struct Annotated<T, M> {
-    value: T,
-    merge: M,
+    pub value: T,
+    merge: std::marker::PhantomData<fn() -> M>,
 }
 
+impl<T, M> Annotated<T, M> {
+    pub fn new(value: T) -> Self {
+        Annotated {
+            value,
+            merge: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Default, M> Default for Annotated<T, M> {
+    fn default() -> Self {
+        Annotated::new(T::default())
+    }
+}
+
+impl<T: Clone, M> Clone for Annotated<T, M> {
+    fn clone(&self) -> Self {
+        Annotated::new(self.value.clone())
+    }
+}
+
+impl<T: std::fmt::Debug, M> std::fmt::Debug for Annotated<T, M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<T, M> std::ops::Deref for Annotated<T, M> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T, M> std::ops::DerefMut for Annotated<T, M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<T: Serialize, M> Serialize for Annotated<T, M> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+impl<'de, T: serde::Deserialize<'de>, M> serde::Deserialize<'de> for Annotated<T, M> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Annotated::new)
+    }
+}
+
+/// Combines the previous value of a state key with a new one, a [`Reducer`] of JSON values.
 pub trait Merger<T> {
     fn merge(prev: T, input: T) -> T;
 }
@@ -89,6 +434,7 @@ pub trait Merge {
     fn merge(prev: Self, input: Self) -> Self;
 }
 
+/// Keeps the new value, what a key without a reducer does.
 pub struct Replace;
 impl<T> Merger<T> for Replace {
     fn merge(_prev: T, input: T) -> T {
@@ -101,9 +447,6 @@ where
     M: Merger<T>,
 {
     fn merge(prev: Self, input: Self) -> Self {
-        Annotated {
-            value: M::merge(prev.value, input.value),
-            merge: prev.merge,
-        }
+        Annotated::new(M::merge(prev.value, input.value))
     }
 }
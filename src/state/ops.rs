@@ -0,0 +1,224 @@
+use modify::Modification;
+use serde_json::Number;
+
+use super::reducer::add;
+use crate::{JsonObject, JsonValue};
+
+/// Merge a patch into the state as RFC 7396 says: objects are merged key by key, `null` removes
+/// the key, anything else replaces the value. A patch that is not an object is ignored, the state
+/// stays an object.
+#[derive(Debug, Clone)]
+pub struct MergePatch(pub JsonValue);
+
+impl Modification<JsonObject> for MergePatch {
+    fn modify(self, value: &mut JsonObject) {
+        if !self.0.is_object() {
+            tracing::warn!("Ignoring a merge patch that is not an object");
+            return;
+        }
+        with_root(value, |root| merge(root, self.0));
+    }
+}
+
+fn merge(target: &mut JsonValue, patch: JsonValue) {
+    let JsonValue::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = JsonValue::Object(JsonObject::new());
+    }
+    let target = target.as_object_mut().expect("made an object");
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge(target.entry(key).or_insert(JsonValue::Null), value);
+        }
+    }
+}
+
+/// Set the value at a JSON Pointer (RFC 6901), the missing objects on the way are created.
+///
+/// `-` as the last token of a pointer into an array pushes the value.
+#[derive(Debug, Clone)]
+pub struct Set {
+    pub pointer: String,
+    pub value: JsonValue,
+}
+
+impl Set {
+    pub fn new(pointer: impl Into<String>, value: impl Into<JsonValue>) -> Self {
+        Set {
+            pointer: pointer.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl Modification<JsonObject> for Set {
+    fn modify(self, value: &mut JsonObject) {
+        with_root(value, |root| {
+            if let Some(target) = entry(root, &self.pointer) {
+                *target = self.value;
+            }
+        });
+    }
+}
+
+/// Remove the value at a JSON Pointer, if there is one.
+#[derive(Debug, Clone)]
+pub struct Remove {
+    pub pointer: String,
+}
+
+impl Remove {
+    pub fn new(pointer: impl Into<String>) -> Self {
+        Remove {
+            pointer: pointer.into(),
+        }
+    }
+}
+
+impl Modification<JsonObject> for Remove {
+    fn modify(self, value: &mut JsonObject) {
+        with_root(value, |root| {
+            let Some((parent, last)) = split_last(&self.pointer) else {
+                return;
+            };
+            match root.pointer_mut(parent) {
+                Some(JsonValue::Object(object)) => {
+                    object.remove(&last);
+                }
+                Some(JsonValue::Array(items)) => {
+                    if let Some(index) = last.parse().ok().filter(|index| *index < items.len()) {
+                        items.remove(index);
+                    }
+                }
+                _ => {}
+            }
+        });
+    }
+}
+
+/// Push a value to the array at a JSON Pointer, a missing array is created.
+#[derive(Debug, Clone)]
+pub struct Push {
+    pub pointer: String,
+    pub value: JsonValue,
+}
+
+impl Push {
+    pub fn new(pointer: impl Into<String>, value: impl Into<JsonValue>) -> Self {
+        Push {
+            pointer: pointer.into(),
+            value: value.into(),
+        }
+    }
+}
+
+impl Modification<JsonObject> for Push {
+    fn modify(self, value: &mut JsonObject) {
+        with_root(value, |root| match entry(root, &self.pointer) {
+            Some(JsonValue::Array(items)) => items.push(self.value),
+            Some(target @ JsonValue::Null) => *target = JsonValue::Array(vec![self.value]),
+            Some(_) => tracing::warn!(pointer = %self.pointer, "Cannot push to a non array value"),
+            None => {}
+        });
+    }
+}
+
+/// Add to the number at a JSON Pointer, a missing value counts as 0.
+///
+/// Integers stay integers unless the sum overflows.
+#[derive(Debug, Clone)]
+pub struct Increment {
+    pub pointer: String,
+    pub by: Number,
+}
+
+impl Increment {
+    /// Anything but a number, a `NaN` float for example, adds 0.
+    pub fn new(pointer: impl Into<String>, by: impl Into<JsonValue>) -> Self {
+        let by = match by.into() {
+            JsonValue::Number(by) => by,
+            _ => Number::from(0),
+        };
+        Increment {
+            pointer: pointer.into(),
+            by,
+        }
+    }
+}
+
+impl Modification<JsonObject> for Increment {
+    fn modify(self, value: &mut JsonObject) {
+        with_root(value, |root| match entry(root, &self.pointer) {
+            Some(target @ JsonValue::Null) => *target = JsonValue::Number(self.by),
+            Some(JsonValue::Number(current)) => {
+                if let Some(sum) = add(current, &self.by) {
+                    *current = sum;
+                }
+            }
+            Some(_) => tracing::warn!(
+                pointer = %self.pointer,
+                "Cannot increment a non number value"
+            ),
+            None => {}
+        });
+    }
+}
+
+/// Run `f` on the state object as a JSON value, `f` must leave it an object.
+fn with_root(object: &mut JsonObject, f: impl FnOnce(&mut JsonValue)) {
+    let mut root = JsonValue::Object(std::mem::take(object));
+    f(&mut root);
+    if let JsonValue::Object(root) = root {
+        *object = root;
+    }
+}
+
+/// The value at `pointer`, created as `null` along with the objects on the way if missing.
+/// `None` if the pointer is malformed, points to the whole state or goes through a value that
+/// can't hold it.
+fn entry<'a>(root: &'a mut JsonValue, pointer: &str) -> Option<&'a mut JsonValue> {
+    let Some(tokens) = pointer.strip_prefix('/') else {
+        tracing::warn!(pointer, "A JSON pointer into the state must start with /");
+        return None;
+    };
+    let mut target = root;
+    for token in tokens.split('/').map(unescape) {
+        if target.is_null() {
+            *target = JsonValue::Object(JsonObject::new());
+        }
+        target = match target {
+            JsonValue::Object(object) => object.entry(token).or_insert(JsonValue::Null),
+            JsonValue::Array(items) if token == "-" => {
+                items.push(JsonValue::Null);
+                items.last_mut().expect("just pushed")
+            }
+            JsonValue::Array(items) => match token.parse::<usize>() {
+                Ok(index) if index < items.len() => &mut items[index],
+                _ => {
+                    tracing::warn!(pointer, token, "No such array index");
+                    return None;
+                }
+            },
+            _ => {
+                tracing::warn!(pointer, token, "Cannot index into a scalar value");
+                return None;
+            }
+        };
+    }
+    Some(target)
+}
+
+/// The pointer to the parent and the unescaped last token.
+fn split_last(pointer: &str) -> Option<(&str, String)> {
+    let (parent, last) = pointer.rsplit_once('/')?;
+    Some((parent, unescape(last)))
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
@@ -0,0 +1,69 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{JsonObject, JsonValue, node::NodeKey};
+
+/// A change of the state as an RFC 6902 JSON Patch, see [`State::recording`](super::State::recording).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatePatch {
+    /// Position of the change among the changes recorded for the same state, from 0.
+    pub sequence: u64,
+    /// The node that made the change, `None` if it was made outside of a node.
+    pub node: Option<NodeKey>,
+    pub patch: json_patch::Patch,
+}
+
+/// Where the handles to a recording state put their patches.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PatchLog {
+    node: Option<NodeKey>,
+    journal: Arc<Mutex<PatchJournal>>,
+}
+
+#[derive(Debug, Default)]
+struct PatchJournal {
+    next_sequence: u64,
+    patches: Vec<StatePatch>,
+}
+
+impl PatchLog {
+    /// The same log, with the changes tagged with `node`.
+    pub(crate) fn made_by(&self, node: NodeKey) -> PatchLog {
+        PatchLog {
+            node: Some(node),
+            journal: self.journal.clone(),
+        }
+    }
+    /// Record the change of the keys of `before`, that holds their previous values, to `after`.
+    pub(crate) fn record(&self, before: BTreeMap<String, Option<JsonValue>>, after: &JsonObject) {
+        let mut old = JsonObject::new();
+        let mut new = JsonObject::new();
+        for (key, value) in before {
+            if let Some(value) = after.get(&key) {
+                new.insert(key.clone(), value.clone());
+            }
+            if let Some(value) = value {
+                old.insert(key, value);
+            }
+        }
+        let patch = json_patch::diff(&JsonValue::Object(old), &JsonValue::Object(new));
+        if patch.0.is_empty() {
+            return;
+        }
+        let mut journal = self.journal.lock().expect("patches poisoned");
+        let sequence = journal.next_sequence;
+        journal.next_sequence += 1;
+        journal.patches.push(StatePatch {
+            sequence,
+            node: self.node.clone(),
+            patch,
+        });
+    }
+    pub(crate) fn take(&self) -> Vec<StatePatch> {
+        std::mem::take(&mut self.journal.lock().expect("patches poisoned").patches)
+    }
+}
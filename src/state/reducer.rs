@@ -0,0 +1,188 @@
+use std::{collections::HashMap, sync::Arc};
+
+use modify::Modification;
+
+use crate::{
+    JsonObject, JsonValue,
+    state::{Merger, StateWrite},
+};
+
+/// Combines an update of a top-level state key with its current value, see
+/// [`Graph::add_reducer`](crate::Graph::add_reducer).
+pub trait Reducer: Send + Sync + 'static {
+    fn reduce(&self, current: Option<JsonValue>, update: JsonValue) -> JsonValue;
+}
+
+impl<M> Reducer for M
+where
+    M: Merger<JsonValue> + Send + Sync + 'static,
+{
+    fn reduce(&self, current: Option<JsonValue>, update: JsonValue) -> JsonValue {
+        M::merge(current.unwrap_or(JsonValue::Null), update)
+    }
+}
+
+/// A reducer from a function of the current value, `null` if unset, and the update.
+pub struct ReduceFn<F>(pub F);
+
+impl<F> Reducer for ReduceFn<F>
+where
+    F: Fn(JsonValue, JsonValue) -> JsonValue + Send + Sync + 'static,
+{
+    fn reduce(&self, current: Option<JsonValue>, update: JsonValue) -> JsonValue {
+        (self.0)(current.unwrap_or(JsonValue::Null), update)
+    }
+}
+
+/// Appends the update to the array, an update that is an array appends all its items.
+pub struct Append;
+
+impl Merger<JsonValue> for Append {
+    fn merge(prev: JsonValue, input: JsonValue) -> JsonValue {
+        let mut items = into_items(prev);
+        items.extend(into_items(input));
+        JsonValue::Array(items)
+    }
+}
+
+impl<T> Merger<Vec<T>> for Append {
+    fn merge(mut prev: Vec<T>, input: Vec<T>) -> Vec<T> {
+        prev.extend(input);
+        prev
+    }
+}
+
+/// Like [`Append`], but skips the items that are already in the array.
+pub struct Union;
+
+impl Merger<JsonValue> for Union {
+    fn merge(prev: JsonValue, input: JsonValue) -> JsonValue {
+        JsonValue::Array(<Union as Merger<Vec<_>>>::merge(
+            into_items(prev),
+            into_items(input),
+        ))
+    }
+}
+
+impl<T: PartialEq> Merger<Vec<T>> for Union {
+    fn merge(mut prev: Vec<T>, input: Vec<T>) -> Vec<T> {
+        for item in input {
+            if !prev.contains(&item) {
+                prev.push(item);
+            }
+        }
+        prev
+    }
+}
+
+/// Adds the update to the number, a value that isn't a number is replaced.
+pub struct Sum;
+
+impl Merger<JsonValue> for Sum {
+    fn merge(prev: JsonValue, input: JsonValue) -> JsonValue {
+        match (&prev, &input) {
+            (JsonValue::Number(a), JsonValue::Number(b)) => {
+                add(a, b).map_or(input, JsonValue::Number)
+            }
+            _ => input,
+        }
+    }
+}
+
+/// The sum of two numbers, an integer unless it overflows. `None` if the sum is not finite.
+pub(crate) fn add(a: &serde_json::Number, b: &serde_json::Number) -> Option<serde_json::Number> {
+    if let Some(sum) = a
+        .as_i64()
+        .zip(b.as_i64())
+        .and_then(|(a, b)| a.checked_add(b))
+    {
+        return Some(sum.into());
+    }
+    serde_json::Number::from_f64(a.as_f64().unwrap_or_default() + b.as_f64().unwrap_or_default())
+}
+
+fn into_items(value: JsonValue) -> Vec<JsonValue> {
+    match value {
+        JsonValue::Null => Vec::new(),
+        JsonValue::Array(items) => items,
+        item => vec![item],
+    }
+}
+
+/// The reducers of a graph by state key.
+#[derive(Clone, Default)]
+pub struct Reducers(Arc<HashMap<String, Arc<dyn Reducer>>>);
+
+impl Reducers {
+    pub fn insert(&mut self, key: String, reducer: Arc<dyn Reducer>) {
+        Arc::make_mut(&mut self.0).insert(key, reducer);
+    }
+    pub fn get(&self, key: &str) -> Option<&dyn Reducer> {
+        self.0.get(key).map(Arc::as_ref)
+    }
+    /// Add the reducers of `other`, they replace the ones of the same keys.
+    pub fn extend(&mut self, other: &Reducers) {
+        if !other.is_empty() {
+            Arc::make_mut(&mut self.0).extend(
+                other
+                    .0
+                    .iter()
+                    .map(|(key, reducer)| (key.clone(), reducer.clone())),
+            );
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<dyn Reducer>)> {
+        self.0.iter().map(|(key, reducer)| (key.as_str(), reducer))
+    }
+    /// The writes `modification` makes to `object`.
+    ///
+    /// Keys with a reducer look unset to the modification, what it writes to them is the update
+    /// handed to the reducer.
+    pub(crate) fn writes<M>(&self, object: &JsonObject, modification: M) -> Vec<StateWrite>
+    where
+        M: Modification<JsonObject>,
+    {
+        let mut updated = object.clone();
+        updated.retain(|key, _| !self.0.contains_key(key));
+        modification.modify(&mut updated);
+        let mut writes = Vec::new();
+        for key in self.0.keys() {
+            if let Some(value) = updated.remove(key) {
+                writes.push(StateWrite::Set {
+                    key: key.clone(),
+                    value,
+                });
+            }
+        }
+        writes.extend(
+            StateWrite::diff(object, &updated)
+                .into_iter()
+                .filter(|write| !self.0.contains_key(write.key())),
+        );
+        writes
+    }
+    /// Apply `write` to `object`, through the reducer of its key if it has one.
+    pub(crate) fn apply(&self, object: &mut JsonObject, write: StateWrite) {
+        match write {
+            StateWrite::Set { key, value } => match self.0.get(&key) {
+                Some(reducer) => {
+                    let current = object.get_mut(&key).map(JsonValue::take);
+                    object.insert(key, reducer.reduce(current, value));
+                }
+                None => {
+                    object.insert(key, value);
+                }
+            },
+            write => write.modify(object),
+        }
+    }
+}
+
+impl std::fmt::Debug for Reducers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
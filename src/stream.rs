@@ -0,0 +1,79 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::{StreamExt, stream::BoxStream};
+
+use crate::{
+    Error, Graph, JsonObject, Response,
+    node::NodeKey,
+    request::Request,
+    run::{RunRecorder, Transition},
+    state::{StatePatch, StateWrite},
+};
+
+/// What [`Graph::stream`] reports about the state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamMode {
+    /// The whole state after each node (or each superstep) completed.
+    #[default]
+    Values,
+    /// The writes of each node as it completes.
+    Updates,
+    /// The changes of the state as JSON Patches, as they are committed.
+    Patches,
+}
+
+/// An event of a running graph.
+#[derive(Debug)]
+pub enum RunEvent {
+    NodeStarted {
+        node: NodeKey,
+    },
+    NodeFinished {
+        node: NodeKey,
+        elapsed: Duration,
+    },
+    /// An edge was taken.
+    Transition(Transition),
+    /// The writes a node made, sent in [`StreamMode::Updates`].
+    Updates {
+        node: NodeKey,
+        writes: Vec<StateWrite>,
+    },
+    /// A change of the state, sent in [`StreamMode::Patches`].
+    Patch(StatePatch),
+    /// A snapshot of the state, sent in [`StreamMode::Values`].
+    Values {
+        state: JsonObject,
+    },
+    /// The run ended, this is always the last event.
+    Finished(Result<Response, Error>),
+}
+
+impl<S> Graph<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Run the graph and watch it through a stream of [`RunEvent`]s.
+    ///
+    /// The run goes on while the stream is polled, dropping the stream stops it.
+    pub fn stream(
+        self: Arc<Self>,
+        request: Request<S>,
+        mode: StreamMode,
+    ) -> BoxStream<'static, RunEvent> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let run = async move {
+            let recorder = RunRecorder::new().with_events(sender.clone(), mode);
+            let result = self.execute(request, recorder, None).await;
+            let _ = sender.unbounded_send(RunEvent::Finished(result));
+        };
+        // the run is driven by the stream, its events come through the channel in order and the
+        // channel closes once the run is done
+        futures::stream::select(
+            receiver.map(Some),
+            futures::stream::once(run).map(|()| None),
+        )
+        .filter_map(futures::future::ready)
+        .boxed()
+    }
+}
@@ -2,7 +2,10 @@ use std::marker::PhantomData;
 
 use serde::de::DeserializeOwned;
 
-use crate::state::View;
+use crate::{
+    request::{FromRequest, Request},
+    state::View,
+};
 
 pub struct TypedState<T>(PhantomData<fn() -> T>);
 
@@ -38,13 +41,146 @@ impl View<crate::JsonObject> for JsonValueView {
     }
 }
 
-// impl<S, T: DeserializeOwned> FromRequest<S> for Json<T> {
-//     fn from_request(request: &Request<S>) -> Result<Self, crate::Error> {
-//         let state = request.state.as_ref().clone().0;
-//         let value = serde_json::from_value(serde_json::Value::Object(state))?;
-//         Ok(Json(value))
-//     }
-// }
+/// The whole state deserialized into `T`.
+#[derive(Debug, Clone, Default)]
+pub struct Json<T>(pub T);
+
+impl<S, T> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+{
+    fn from_request(request: &Request<S>) -> Result<Self, crate::Error> {
+        read("Json", request, |object| {
+            TypedState::<T>::new().view(object)
+        })?
+        .map(Json)
+        .map_err(|error| crate::Error::ExtractError {
+            extractor: "Json",
+            reason: error.to_string(),
+        })
+    }
+}
+
+/// Names a part of the state for [`Field`] and [`Pointer`], define one with
+/// [`state_key!`](crate::state_key).
+pub trait StateKey {
+    /// A top-level key for [`Field`], a JSON Pointer for [`Pointer`].
+    const KEY: &'static str;
+}
+
+/// The top-level field `K` of the state deserialized into `T`.
+///
+/// A missing field is an error, unless `T` is an `Option`.
+pub struct Field<K, T> {
+    pub value: T,
+    key: PhantomData<fn() -> K>,
+}
+
+impl<S, K, T> FromRequest<S> for Field<K, T>
+where
+    K: StateKey,
+    T: DeserializeOwned,
+{
+    fn from_request(request: &Request<S>) -> Result<Self, crate::Error> {
+        let value = read("Field", request, |object| object.get(K::KEY).cloned())?;
+        let value = extract("Field", value, || format!("key {:?}", K::KEY))?;
+        Ok(Field {
+            value,
+            key: PhantomData,
+        })
+    }
+}
+
+/// The value at the JSON Pointer `K` into the state, `/user/name` for example, deserialized into
+/// `T`.
+///
+/// A missing value is an error, unless `T` is an `Option`.
+pub struct Pointer<K, T> {
+    pub value: T,
+    pointer: PhantomData<fn() -> K>,
+}
+
+impl<S, K, T> FromRequest<S> for Pointer<K, T>
+where
+    K: StateKey,
+    T: DeserializeOwned,
+{
+    fn from_request(request: &Request<S>) -> Result<Self, crate::Error> {
+        let value = read("Pointer", request, |object| lookup(object, K::KEY))?;
+        let value = extract("Pointer", value, || format!("pointer {:?}", K::KEY))?;
+        Ok(Pointer {
+            value,
+            pointer: PhantomData,
+        })
+    }
+}
+
+/// The value at `pointer`, without copying the whole state into a JSON value.
+fn lookup(object: &crate::JsonObject, pointer: &str) -> Option<crate::JsonValue> {
+    let pointer = pointer.strip_prefix('/')?;
+    let (key, rest) = pointer.split_at(pointer.find('/').unwrap_or(pointer.len()));
+    let key = key.replace("~1", "/").replace("~0", "~");
+    object.get(&key)?.pointer(rest).cloned()
+}
+
+/// Read the state of `request` for `extractor`, see [`Request::read_state`].
+fn read<S, R>(
+    extractor: &'static str,
+    request: &Request<S>,
+    read: impl FnOnce(&crate::JsonObject) -> R,
+) -> Result<R, crate::Error> {
+    request
+        .read_state(read)
+        .ok_or_else(|| crate::Error::ExtractError {
+            extractor,
+            reason: "the request has no snapshot of the state".to_string(),
+        })
+}
+
+/// Deserialize an extracted `value`, `what` names where it was looked up for the errors.
+fn extract<T: DeserializeOwned>(
+    extractor: &'static str,
+    value: Option<crate::JsonValue>,
+    what: impl FnOnce() -> String,
+) -> Result<T, crate::Error> {
+    let missing = value.is_none();
+    serde_json::from_value(value.unwrap_or_default()).map_err(|error| crate::Error::ExtractError {
+        extractor,
+        reason: if missing {
+            format!("the state has nothing at {}", what())
+        } else {
+            format!("{} does not hold the expected type: {error}", what())
+        },
+    })
+}
+
+macro_rules! impl_deref {
+    ($($name:ident),*) => {
+        $(
+            impl<K, T> $name<K, T> {
+                pub fn into_inner(self) -> T {
+                    self.value
+                }
+            }
+
+            impl<K, T> std::ops::Deref for $name<K, T> {
+                type Target = T;
+
+                fn deref(&self) -> &Self::Target {
+                    &self.value
+                }
+            }
+
+            impl<K, T: std::fmt::Debug> std::fmt::Debug for $name<K, T> {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    self.value.fmt(f)
+                }
+            }
+        )*
+    };
+}
+
+impl_deref!(Field, Pointer);
 
 // impl<T: Serialize> IntoStateModification for Json<T> {
 //     fn into_state(self) -> Result<crate::state::State, crate::Error> {
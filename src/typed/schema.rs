@@ -0,0 +1,25 @@
+use modify::Modification;
+use schemars::JsonSchema;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{JsonObject, state::Reducers, typed::json::TypedState};
+
+/// A typed schema of the state, derive it with `#[derive(GraphState)]` and register it with
+/// [`Graph::set_state`](crate::Graph::set_state).
+pub trait GraphState: Serialize + DeserializeOwned + JsonSchema + Send + Sync + 'static {
+    /// A partial update of the state, only the fields that are set are written.
+    type Update: Modification<JsonObject> + Default + Send + 'static;
+    /// The reducers declared on the fields, by key.
+    fn reducers() -> Reducers;
+    /// Read the state as `Self`, see [`State::fetch_view`](crate::state::State::fetch_view).
+    fn view() -> TypedState<Self> {
+        TypedState::new()
+    }
+    /// An empty update to set fields on.
+    fn update() -> Self::Update {
+        Self::Update::default()
+    }
+    fn schema() -> schemars::Schema {
+        schemars::schema_for!(Self)
+    }
+}
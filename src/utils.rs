@@ -54,4 +54,19 @@ macro_rules! map {
             $(($key, $val)),*
         ])
     };
-}
\ No newline at end of file
+}
+
+/// Define types naming parts of the state, for [`Field`](crate::typed::json::Field) and
+/// [`Pointer`](crate::typed::json::Pointer): `state_key!(pub Query = "query");`.
+#[macro_export]
+macro_rules! state_key {
+    ($($vis:vis $name:ident = $key:expr);* $(;)?) => {
+        $(
+            $vis enum $name {}
+
+            impl $crate::typed::json::StateKey for $name {
+                const KEY: &'static str = $key;
+            }
+        )*
+    };
+}
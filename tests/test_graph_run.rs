@@ -4,13 +4,26 @@ use std::sync::{
 };
 
 use crabgraph::{
-    Context, Graph, JsonObject, map,
-    node::{IntoNode, Node, NodeKey},
-    state::State,
-    typed::json::TypedState,
+    Context, Error, Graph, GraphError, GraphState, JsonObject,
+    cancel::CancellationToken,
+    checkpoint::{Checkpointer, FileCheckpointer, InMemoryCheckpointer},
+    edge::{Dispatch, FanOut, Goto},
+    extension::Extension,
+    interrupt::{Interrupt, InterruptKind, Resume},
+    join::Join,
+    map,
+    node::{Command, IntoNode, Node, NodeKey, NodeOptions},
+    request::{Input, NodeFailure},
+    retry::RetryPolicy,
+    run::ExecutionMode,
+    state::{Append, Increment, MergePatch, Push, ReduceFn, Remove, Set, State, Sum},
+    state_key,
+    stream::{RunEvent, StreamMode},
+    typed::json::{Field, Json, Pointer, TypedState},
 };
 
-use modify::{ModificationLayerExt, apply, call};
+use futures::StreamExt;
+use modify::{Modification, ModificationLayerExt, apply, call};
 use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Default)]
 pub struct App {
@@ -115,3 +128,1234 @@ async fn print_if_odd(state: State) -> &'static str {
 
     if index % 2 == 1 { "odd" } else { "even" }
 }
+
+#[tokio::test]
+async fn test_join_all() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node(ADD_LOG, add_log)
+        .add_node(INCREASE_COUNTER, increase_counter)
+        .add_node(PRINT_STATE, count_calls)
+        .add_edge(NodeKey::Start, [ADD_LOG, INCREASE_COUNTER])
+        .add_edge(ADD_LOG, PRINT_STATE)
+        .add_edge(INCREASE_COUNTER, PRINT_STATE)
+        .add_edge(PRINT_STATE, NodeKey::End)
+        .set_join(PRINT_STATE, Join::All);
+    let graph = graph.compile()?;
+    let response = graph.run(context.new_request(Default::default())).await?;
+    // increase_counter ran once, count_calls once
+    assert_eq!(context.state.countor.load(Ordering::SeqCst), 2);
+    assert_eq!(response.executions.len(), 3);
+    assert_eq!(response.executions.last().unwrap().node, PRINT_STATE);
+    assert_eq!(response.terminal_edges().count(), 1);
+    assert_eq!(response.state["__log"], serde_json::json!("hello world"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_superstep() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node(ADD_LOG, add_log)
+        .add_node(INCREASE_COUNTER, increase_counter)
+        .add_node(PRINT_STATE, count_calls)
+        .add_edge(NodeKey::Start, [ADD_LOG, INCREASE_COUNTER])
+        .add_edge(ADD_LOG, PRINT_STATE)
+        .add_edge(INCREASE_COUNTER, PRINT_STATE)
+        .add_edge(PRINT_STATE, NodeKey::End)
+        .set_mode(ExecutionMode::Superstep);
+    let graph = graph.compile()?;
+    let request = context.new_request(Default::default());
+    graph.run(request.clone()).await?;
+    // both writes of the first step are merged, print_state runs once in the second step
+    assert_eq!(context.state.countor.load(Ordering::SeqCst), 2);
+    let state = request.state.snapshot().await;
+    assert_eq!(state["__log"], serde_json::json!("hello world"));
+    assert_eq!(state["index"], serde_json::json!(0));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recursion_limit() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node(INCREASE_COUNTER, count_calls)
+        .add_edge(NodeKey::Start, INCREASE_COUNTER)
+        .add_edge(INCREASE_COUNTER, [INCREASE_COUNTER, NodeKey::End])
+        .set_recursion_limit(5);
+    let graph = graph.compile()?;
+    let result = graph.run(context.new_request(Default::default())).await;
+    match result {
+        Err(Error::GraphError(GraphError::RecursionLimitExceeded {
+            limit,
+            node,
+            history,
+        })) => {
+            assert_eq!(limit, 5);
+            assert_eq!(node, INCREASE_COUNTER);
+            assert_eq!(history.len(), 5);
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+    assert_eq!(context.state.countor.load(Ordering::SeqCst), 5);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_updates() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node(ADD_LOG, add_log)
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, NodeKey::End);
+    let graph = graph.compile()?;
+    let events = graph
+        .stream(context.new_request(Default::default()), StreamMode::Updates)
+        .collect::<Vec<_>>()
+        .await;
+    // the transition out of Start comes before the first node starts
+    let first_started = events
+        .iter()
+        .find(|event| matches!(event, RunEvent::NodeStarted { .. }));
+    assert!(matches!(first_started, Some(RunEvent::NodeStarted { node }) if *node == ADD_LOG));
+    let updates = events
+        .iter()
+        .find_map(|event| match event {
+            RunEvent::Updates { node, writes } if *node == ADD_LOG => Some(writes),
+            _ => None,
+        })
+        .expect("add_log reports its writes");
+    assert_eq!(updates.len(), 1);
+    assert!(matches!(events.last(), Some(RunEvent::Finished(Ok(_)))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancellation() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node(PRINT_STATE, wait_for_cancel)
+        .add_edge(NodeKey::Start, PRINT_STATE)
+        .add_edge(PRINT_STATE, NodeKey::End);
+    let graph = graph.compile()?;
+    let cancellation = CancellationToken::new();
+    let run = tokio::spawn(
+        graph.run(
+            context
+                .new_request(Default::default())
+                .with_cancellation(cancellation.clone()),
+        ),
+    );
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    cancellation.cancel();
+    match run.await? {
+        Err(Error::Cancelled { in_flight }) => {
+            assert_eq!(in_flight, [PRINT_STATE].into_iter().collect());
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+    Ok(())
+}
+
+async fn wait_for_cancel(cancellation: CancellationToken) -> Result<(), crabgraph::NodeError> {
+    cancellation.cancelled().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_node_timeout() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node_with(
+            PRINT_STATE,
+            wait_for_cancel,
+            NodeOptions::new().with_timeout(std::time::Duration::from_millis(20)),
+        )
+        .add_edge(NodeKey::Start, PRINT_STATE)
+        .add_edge(PRINT_STATE, NodeKey::End);
+    let graph = graph.compile()?;
+    match graph.run(context.new_request(Default::default())).await {
+        Err(Error::NodeTimeout { node_key, .. }) => assert_eq!(node_key, PRINT_STATE),
+        other => panic!("unexpected result: {other:?}"),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_retry() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node_with(
+            INCREASE_COUNTER,
+            fail_first_attempt,
+            NodeOptions::new().with_retry(RetryPolicy::new(2).with_jitter(false).with_backoff(
+                std::time::Duration::from_millis(1),
+                2.0,
+                std::time::Duration::from_millis(10),
+            )),
+        )
+        .add_edge(NodeKey::Start, INCREASE_COUNTER)
+        .add_edge(INCREASE_COUNTER, NodeKey::End);
+    let graph = graph.compile()?;
+    let response = graph.run(context.new_request(Default::default())).await?;
+    assert_eq!(context.state.countor.load(Ordering::SeqCst), 2);
+    // the write of the failed attempt is gone
+    assert_eq!(response.state.get("failed"), None);
+    assert_eq!(response.state["index"], serde_json::json!(1));
+    Ok(())
+}
+
+async fn fail_first_attempt(
+    context: Context<App>,
+    state: State,
+) -> Result<(), crabgraph::NodeError> {
+    let index = context.state.countor.fetch_add(1, Ordering::SeqCst);
+    if index == 0 {
+        state
+            .apply_modification(Insert("failed", serde_json::json!(true)))
+            .await;
+        return Err("first attempt fails".into());
+    }
+    state
+        .apply_modification(Insert("index", serde_json::json!(index)))
+        .await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_error_edge() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        graph
+            .set_mode(mode)
+            .add_node(INCREASE_COUNTER, always_fail)
+            .add_node(PRINT_STATE, record_failure)
+            .add_edge(NodeKey::Start, INCREASE_COUNTER)
+            .add_edge(INCREASE_COUNTER, NodeKey::End)
+            .add_error_edge(INCREASE_COUNTER, PRINT_STATE)
+            .add_edge(PRINT_STATE, NodeKey::End);
+        let graph = graph.compile()?;
+        let response = graph.run(context.new_request(Default::default())).await?;
+        assert_eq!(
+            response.state["failed_node"],
+            serde_json::json!(INCREASE_COUNTER.to_string())
+        );
+        assert!(response.state.get("half_done").is_none());
+        assert!(
+            response
+                .path
+                .iter()
+                .any(|t| t.from == INCREASE_COUNTER && t.to == PRINT_STATE)
+        );
+    }
+    Ok(())
+}
+
+async fn always_fail(state: State) -> Result<(), crabgraph::NodeError> {
+    // rolled back with the failure
+    state
+        .apply_modification(Insert("half_done", serde_json::json!(true)))
+        .await;
+    Err("search is down".into())
+}
+
+async fn record_failure(state: State, failure: NodeFailure) -> Result<(), crabgraph::NodeError> {
+    assert!(matches!(*failure.error, Error::NodeExecutionError(_)));
+    state
+        .apply_modification(Insert(
+            "failed_node",
+            serde_json::json!(failure.node_key.to_string()),
+        ))
+        .await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrency_limit() -> anyhow::Result<()> {
+    for use_resource in [false, true] {
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        let options = if use_resource {
+            graph.add_resource("llm", 1);
+            NodeOptions::new().with_resource("llm")
+        } else {
+            graph.set_max_concurrency(1);
+            NodeOptions::new()
+        };
+        graph
+            .add_node_with(INCREASE_COUNTER, run_alone, options.clone())
+            .add_node_with(ADD_LOG, run_alone, options)
+            .add_edge(NodeKey::Start, INCREASE_COUNTER)
+            .add_edge(NodeKey::Start, ADD_LOG)
+            .add_edge(INCREASE_COUNTER, NodeKey::End)
+            .add_edge(ADD_LOG, NodeKey::End);
+        let graph = graph.compile()?;
+        let response = graph.run(context.new_request(Default::default())).await?;
+        assert_eq!(response.executions.len(), 2);
+    }
+    let mut graph = crate::Graph::<App>::new();
+    graph
+        .add_node_with(ADD_LOG, run_alone, NodeOptions::new().with_resource("gpu"))
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, NodeKey::End);
+    assert!(matches!(
+        graph.check(),
+        Err(GraphError::UndefinedResource { .. })
+    ));
+    Ok(())
+}
+
+/// Fails if another `run_alone` node of the limited set runs at the same time.
+async fn run_alone(context: Context<App>) -> Result<(), crabgraph::NodeError> {
+    if context.state.countor.fetch_add(1, Ordering::SeqCst) > 0 {
+        return Err("nodes ran concurrently".into());
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    context.state.countor.fetch_sub(1, Ordering::SeqCst);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_checkpoint_resume() -> anyhow::Result<()> {
+    let directory = std::env::temp_dir().join(format!("crabgraph-test-{}", std::process::id()));
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        graph
+            .set_mode(mode)
+            .add_node(ADD_LOG, mark_visited)
+            .add_node(INCREASE_COUNTER, fail_first_attempt)
+            .add_edge(NodeKey::Start, ADD_LOG)
+            .add_edge(ADD_LOG, INCREASE_COUNTER)
+            .add_edge(INCREASE_COUNTER, NodeKey::End);
+        match mode {
+            ExecutionMode::Async => graph.set_checkpointer(InMemoryCheckpointer::new()),
+            ExecutionMode::Superstep => graph.set_checkpointer(FileCheckpointer::new(&directory)),
+        };
+        let graph = graph.compile()?;
+        let request = || {
+            context
+                .new_request(Default::default())
+                .with_thread_id("research-1")
+        };
+        assert!(graph.clone().run(request()).await.is_err());
+        let response = graph.clone().resume(request()).await?;
+        // only the failed node runs again
+        assert_eq!(response.executions.len(), 1);
+        assert_eq!(response.state["visited"], serde_json::json!(true));
+        assert_eq!(response.state["index"], serde_json::json!(1));
+        let history = graph
+            .checkpointer
+            .as_ref()
+            .expect("checkpointer is set")
+            .list("research-1")
+            .await?;
+        assert!(history.last().expect("has checkpoints").frontier.is_empty());
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_interrupt() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        for before in [true, false] {
+            let mut graph = crate::Graph::<App>::new();
+            let context = Context::<App>::default();
+            graph
+                .set_mode(mode)
+                .add_node(ADD_LOG, mark_visited)
+                .add_node(INCREASE_COUNTER, ask_approval)
+                .add_edge(NodeKey::Start, ADD_LOG)
+                .add_edge(ADD_LOG, INCREASE_COUNTER)
+                .add_edge(INCREASE_COUNTER, NodeKey::End);
+            if before {
+                graph.interrupt_before(ADD_LOG);
+            }
+            let graph = graph.compile()?;
+            let response = graph
+                .clone()
+                .run(context.new_request(Default::default()))
+                .await?;
+            let interrupted = response.interrupted.expect("run is interrupted");
+            if before {
+                assert_eq!(interrupted.kind, InterruptKind::Before);
+                assert_eq!(interrupted.frontier(), [ADD_LOG]);
+                assert_eq!(response.state.get("visited"), None);
+            } else {
+                assert_eq!(interrupted.kind, InterruptKind::Node);
+                assert_eq!(interrupted.payload, Some(serde_json::json!("approve?")));
+                assert_eq!(interrupted.frontier(), [INCREASE_COUNTER]);
+                assert_eq!(response.state["visited"], serde_json::json!(true));
+            }
+            let request = || {
+                context
+                    .new_request(Default::default())
+                    .with_resume(serde_json::json!("yes"))
+            };
+            let mut response = graph
+                .clone()
+                .run_from_checkpoint(request(), interrupted.checkpoint)
+                .await?;
+            if before {
+                // the resume value is for add_log only, increase_counter still asks
+                let interrupted = response.interrupted.expect("run is interrupted again");
+                assert_eq!(interrupted.kind, InterruptKind::Node);
+                assert_eq!(interrupted.frontier(), [INCREASE_COUNTER]);
+                response = graph
+                    .run_from_checkpoint(request(), interrupted.checkpoint)
+                    .await?;
+            }
+            assert!(response.interrupted.is_none());
+            assert_eq!(response.state["visited"], serde_json::json!(true));
+            assert_eq!(response.state["approved"], serde_json::json!("yes"));
+        }
+    }
+    Ok(())
+}
+
+async fn ask_approval(state: State, Resume(value): Resume) -> Result<(), crabgraph::NodeError> {
+    let Some(value) = value else {
+        return Err(Interrupt::new(serde_json::json!("approve?")).into());
+    };
+    state.apply_modification(Insert("approved", value)).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_time_travel() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .set_checkpointer(InMemoryCheckpointer::new())
+        .add_node(ADD_LOG, mark_visited)
+        .add_node(INCREASE_COUNTER, count_calls)
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, INCREASE_COUNTER)
+        .add_edge(INCREASE_COUNTER, NodeKey::End);
+    let graph = graph.compile()?;
+    let request = || {
+        context
+            .new_request(Default::default())
+            .with_thread_id("thread")
+    };
+    graph.clone().run(request()).await?;
+    let history = graph.state_history("thread").await?;
+    let before_counter = history
+        .iter()
+        .find(|checkpoint| checkpoint.frontier == [INCREASE_COUNTER])
+        .expect("checkpoint before increase_counter");
+    assert_eq!(before_counter.state["visited"], serde_json::json!(true));
+    let fork = graph
+        .update_state(
+            "thread",
+            &before_counter.checkpoint_id,
+            Insert("visited", serde_json::json!(false)),
+        )
+        .await?;
+    assert_eq!(
+        fork.parent_id.as_deref(),
+        Some(before_counter.checkpoint_id.as_str())
+    );
+    let response = graph.clone().run_from_checkpoint(request(), fork).await?;
+    // only the node after the fork point runs again
+    assert_eq!(response.executions.len(), 1);
+    assert_eq!(context.state.countor.load(Ordering::SeqCst), 2);
+    assert_eq!(response.state["visited"], serde_json::json!(false));
+    let latest = graph
+        .get_state("thread", None)
+        .await?
+        .expect("has checkpoints");
+    assert!(latest.frontier.is_empty());
+    Ok(())
+}
+
+async fn mark_visited(state: State) -> Result<(), crabgraph::NodeError> {
+    state
+        .apply_modification(Insert("visited", serde_json::json!(true)))
+        .await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fan_out() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        graph
+            .set_mode(mode)
+            .add_reducer("sum", Sum)
+            .add_node(ADD_LOG, count_calls)
+            .add_node(INCREASE_COUNTER, add_input)
+            .add_edge(NodeKey::Start, ADD_LOG)
+            .add_edge(
+                ADD_LOG,
+                FanOut::new([INCREASE_COUNTER], || {
+                    (1..=3)
+                        .map(|n| Dispatch::new(INCREASE_COUNTER, serde_json::json!(n)))
+                        .collect::<Vec<_>>()
+                }),
+            )
+            .add_edge(INCREASE_COUNTER, NodeKey::End);
+        let graph = graph.compile()?;
+        let response = graph.run(context.new_request(Default::default())).await?;
+        // every dispatch is an invocation of its own
+        assert_eq!(response.state["sum"], serde_json::json!(6));
+        assert_eq!(
+            response
+                .executions
+                .iter()
+                .filter(|e| e.node == INCREASE_COUNTER)
+                .count(),
+            3
+        );
+    }
+    Ok(())
+}
+
+async fn add_input(Input(n): Input<i64>) -> Insert {
+    Insert("sum", serde_json::json!(n))
+}
+
+#[tokio::test]
+async fn test_reducers() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .set_mode(ExecutionMode::Superstep)
+        .add_reducer("log", Append)
+        .add_reducer(
+            "max",
+            ReduceFn(|current: serde_json::Value, update: serde_json::Value| {
+                if current.as_i64() > update.as_i64() {
+                    current
+                } else {
+                    update
+                }
+            }),
+        )
+        .add_node(ADD_LOG, || async {
+            (
+                Insert("log", serde_json::json!(["a"])),
+                Insert("max", serde_json::json!(1)),
+            )
+        })
+        .add_node(PRINT_STATE, || async {
+            (
+                Insert("log", serde_json::json!("b")),
+                Insert("max", serde_json::json!(3)),
+            )
+        })
+        .add_node(INCREASE_COUNTER, || async {
+            (
+                Insert("log", serde_json::json!(["c", "d"])),
+                Insert("max", serde_json::json!(2)),
+            )
+        })
+        .add_edge(NodeKey::Start, [ADD_LOG, PRINT_STATE])
+        .add_edge(ADD_LOG, INCREASE_COUNTER)
+        .add_edge(PRINT_STATE, NodeKey::End)
+        .add_edge(INCREASE_COUNTER, NodeKey::End);
+    let graph = graph.compile()?;
+    let response = graph.run(context.new_request(Default::default())).await?;
+    // the writes of the parallel nodes are combined instead of the last one winning
+    assert_eq!(
+        response.state["log"],
+        serde_json::json!(["a", "b", "c", "d"])
+    );
+    assert_eq!(response.state["max"], serde_json::json!(3));
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, schemars::JsonSchema, GraphState)]
+struct Research {
+    #[serde(default)]
+    #[reducer(Append)]
+    notes: Vec<String>,
+    #[serde(default)]
+    #[reducer(Sum)]
+    loops: u32,
+    #[serde(default)]
+    topic: String,
+}
+
+#[tokio::test]
+async fn test_derive_graph_state() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .set_mode(ExecutionMode::Superstep)
+        .set_state::<Research>()
+        .add_node(ADD_LOG, || async {
+            ResearchUpdate::default()
+                .notes(vec!["a".to_string()])
+                .loops(1)
+        })
+        .add_node(PRINT_STATE, || async {
+            ResearchUpdate::default()
+                .notes(vec!["b".to_string()])
+                .loops(1)
+                .topic("crabs".to_string())
+        })
+        .add_edge(NodeKey::Start, [ADD_LOG, PRINT_STATE])
+        .add_edge(ADD_LOG, NodeKey::End)
+        .add_edge(PRINT_STATE, NodeKey::End);
+    assert!(graph.state_schema.is_some());
+    let graph = graph.compile()?;
+    let response = graph
+        .run_typed::<Research>(context.new_request(Default::default()))
+        .await?;
+    assert_eq!(response.output.notes, ["a", "b"]);
+    assert_eq!(response.output.loops, 2);
+    assert_eq!(response.output.topic, "crabs");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_command() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        graph
+            .set_mode(mode)
+            .add_node(ADD_LOG, route_to_print)
+            .add_node(PRINT_STATE, print_state)
+            .add_node(INCREASE_COUNTER, count_calls)
+            .add_edge(NodeKey::Start, ADD_LOG)
+            .add_edge(ADD_LOG, Goto::new([PRINT_STATE, INCREASE_COUNTER]))
+            .add_edge(PRINT_STATE, NodeKey::End)
+            .add_edge(INCREASE_COUNTER, NodeKey::End);
+        let graph = graph.compile()?;
+        let response = graph.run(context.new_request(Default::default())).await?;
+        assert_eq!(response.state["routed"], serde_json::json!(true));
+        assert!(
+            response
+                .path
+                .iter()
+                .any(|t| t.from == ADD_LOG && t.to == PRINT_STATE)
+        );
+        assert_eq!(context.state.countor.load(Ordering::SeqCst), 0);
+    }
+    Ok(())
+}
+
+async fn route_to_print() -> Result<Command, crabgraph::NodeError> {
+    Ok(Command::goto(PRINT_STATE).with_update(Insert("routed", serde_json::json!(true))))
+}
+
+#[tokio::test]
+async fn test_modification_output() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        graph
+            .set_mode(mode)
+            .add_node(ADD_LOG, greet)
+            .add_node(PRINT_STATE, maybe_greet_again)
+            .add_edge(NodeKey::Start, ADD_LOG)
+            .add_edge(ADD_LOG, PRINT_STATE)
+            .add_edge(PRINT_STATE, NodeKey::End);
+        let graph = graph.compile()?;
+        let response = graph.run(context.new_request(Default::default())).await?;
+        assert_eq!(response.state["greeting"], serde_json::json!("hello"));
+        assert_eq!(response.state.as_object().map(|o| o.len()), Some(1));
+    }
+    Ok(())
+}
+
+async fn greet() -> Result<Insert, crabgraph::NodeError> {
+    Ok(Insert("greeting", serde_json::json!("hello")))
+}
+
+async fn maybe_greet_again(state: State) -> Option<Insert> {
+    let greeted = state.snapshot().await.contains_key("greeting");
+    (!greeted).then(|| Insert("greeting", serde_json::json!("hello again")))
+}
+
+struct Insert(&'static str, serde_json::Value);
+
+impl Modification<JsonObject> for Insert {
+    fn modify(self, value: &mut JsonObject) {
+        value.insert(self.0.to_string(), self.1);
+    }
+}
+
+async fn count_calls(context: Context<App>) -> Result<(), crabgraph::NodeError> {
+    context.state.countor.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_detect_conflicts() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        for reduced in [false, true] {
+            let mut graph = crate::Graph::<App>::new();
+            let context = Context::<App>::default();
+            graph
+                .set_mode(mode)
+                .set_detect_conflicts(true)
+                .add_node(ADD_LOG, write_after_nap)
+                .add_node(PRINT_STATE, write_after_nap)
+                .add_edge(NodeKey::Start, [ADD_LOG, PRINT_STATE])
+                .add_edge(ADD_LOG, NodeKey::End)
+                .add_edge(PRINT_STATE, NodeKey::End);
+            if reduced {
+                graph.add_reducer("written", Sum);
+            }
+            let graph = graph.compile()?;
+            let result = graph.run(context.new_request(Default::default())).await;
+            if reduced {
+                assert_eq!(result?.state["written"], serde_json::json!(2));
+            } else {
+                let Err(Error::InvalidConcurrentUpdate { key, first, second }) = result else {
+                    panic!("expected a conflict, got {result:?}");
+                };
+                assert_eq!(key, "written");
+                let mut nodes = [first, second];
+                nodes.sort();
+                assert_eq!(nodes, [ADD_LOG, PRINT_STATE]);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn write_after_nap() -> Insert {
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    Insert("written", serde_json::json!(1))
+}
+
+#[tokio::test]
+async fn test_snapshot_reads() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        graph
+            .set_mode(mode)
+            .add_node(ADD_LOG, write_after_nap)
+            .add_node(PRINT_STATE, read_twice)
+            .add_edge(NodeKey::Start, [ADD_LOG, PRINT_STATE])
+            .add_edge(ADD_LOG, NodeKey::End)
+            .add_edge(PRINT_STATE, NodeKey::End);
+        let graph = graph.compile()?;
+        let response = graph.run(context.new_request(Default::default())).await?;
+        assert_eq!(response.state["written"], serde_json::json!(1));
+        assert_eq!(response.state["reads"], serde_json::json!([false, false]));
+    }
+    Ok(())
+}
+
+async fn read_twice(state: State) -> Insert {
+    let first = state.snapshot().await.contains_key("written");
+    tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+    let second = state.snapshot().await.contains_key("written");
+    Insert("reads", serde_json::json!([first, second]))
+}
+
+#[tokio::test]
+async fn test_patches() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_reducer("log", Append)
+        .add_node(ADD_LOG, || async {
+            Insert("log", serde_json::json!({ "node": "add_log" }))
+        })
+        .add_node(PRINT_STATE, || async {
+            (
+                Insert("log", serde_json::json!({ "node": "print_state" })),
+                Insert("printed", serde_json::json!(true)),
+            )
+        })
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, PRINT_STATE)
+        .add_edge(PRINT_STATE, NodeKey::End);
+    let graph = graph.compile()?;
+    let response = graph.run(context.new_request(Default::default())).await?;
+    let nodes: Vec<_> = response
+        .patches
+        .iter()
+        .map(|patch| patch.node.clone())
+        .collect();
+    assert_eq!(nodes, [Some(ADD_LOG), Some(PRINT_STATE)]);
+    assert!(
+        response
+            .patches
+            .iter()
+            .enumerate()
+            .all(|(index, patch)| patch.sequence == index as u64)
+    );
+    // the patches rebuild the state from the empty one the run started with
+    let rebuilt = State::default();
+    for patch in &response.patches {
+        rebuilt.apply_patch(patch).await?;
+    }
+    assert_eq!(
+        serde_json::Value::Object(rebuilt.snapshot().await),
+        response.state
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_state_modifications() -> anyhow::Result<()> {
+    let state = State::from_json_value(serde_json::json!({
+        "user": { "name": "crab", "email": "crab@sea" },
+        "steps": 1,
+    }));
+    state
+        .apply_modification(MergePatch(
+            serde_json::json!({ "user": { "email": null, "legs": 10 } }),
+        ))
+        .await;
+    state
+        .apply_modification((
+            Set::new("/search/query", "rust"),
+            Push::new("/search/results", "crates.io"),
+        ))
+        .await;
+    state
+        .apply_modification((
+            Push::new("/search/results", "docs.rs"),
+            Remove::new("/search/results/0"),
+        ))
+        .await;
+    state
+        .apply_modification((Increment::new("/steps", 2), Increment::new("/cost", 0.5)))
+        .await;
+    assert_eq!(
+        serde_json::Value::Object(state.snapshot().await),
+        serde_json::json!({
+            "user": { "name": "crab", "legs": 10 },
+            "search": { "query": "rust", "results": ["docs.rs"] },
+            "steps": 3,
+            "cost": 0.5,
+        })
+    );
+    Ok(())
+}
+
+state_key! {
+    Query = "query";
+    UserName = "/user/name";
+    Missing = "missing";
+}
+
+#[derive(Debug, Deserialize)]
+struct Search {
+    query: String,
+}
+
+#[tokio::test]
+async fn test_extractors() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node(
+            ADD_LOG,
+            |query: Field<Query, String>,
+             name: Pointer<UserName, String>,
+             missing: Field<Missing, Option<i64>>,
+             Json(search): Json<Search>| async move {
+                assert_eq!(*query, search.query);
+                assert_eq!(missing.into_inner(), None);
+                Set::new("/greeting", format!("{} searched {}", *name, *query))
+            },
+        )
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, NodeKey::End);
+    let graph = graph.compile()?;
+    let state = State::from_json_value(serde_json::json!({
+        "query": "rust",
+        "user": { "name": "crab" },
+    }));
+    let response = graph.run(context.new_request(state)).await?;
+    assert_eq!(
+        response.state["greeting"],
+        serde_json::json!("crab searched rust")
+    );
+
+    let mut graph = crate::Graph::<App>::new();
+    graph
+        .add_node(PRINT_STATE, require_missing)
+        .add_edge(NodeKey::Start, PRINT_STATE)
+        .add_edge(PRINT_STATE, NodeKey::End);
+    let graph = graph.compile()?;
+    let result = graph.run(context.new_request(Default::default())).await;
+    let Err(Error::ExtractError { extractor, reason }) = result else {
+        panic!("expected an extract error, got {result:?}");
+    };
+    assert_eq!(extractor, "Field");
+    assert!(reason.contains("\"missing\""), "{reason}");
+    Ok(())
+}
+
+async fn require_missing(_: Field<Missing, i64>) -> Result<(), crabgraph::NodeError> {
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct Greeting(&'static str);
+
+#[tokio::test]
+async fn test_extension() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    graph
+        .add_node(ADD_LOG, |Extension(greeting): Extension<Greeting>| async move {
+            Set::new("/greeting", greeting.0)
+        })
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, NodeKey::End);
+    let graph = graph.compile()?;
+    let context = Context::<App>::default().with_extension(Greeting("hello"));
+    let response = graph
+        .clone()
+        .run(context.new_request(Default::default()))
+        .await?;
+    assert_eq!(response.state["greeting"], serde_json::json!("hello"));
+    let result = graph
+        .run(Context::<App>::default().new_request(Default::default()))
+        .await;
+    assert!(matches!(
+        result,
+        Err(Error::ExtractError {
+            extractor: "Extension",
+            ..
+        })
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subgraph_interrupt() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        let mut subgraph = crate::Graph::<App>::new();
+        subgraph
+            .set_mode(mode)
+            .add_node("count", count_calls)
+            .add_node(PRINT_STATE, mark_visited)
+            .add_node(INCREASE_COUNTER, ask_approval)
+            .add_node("confirm", ask_confirmation)
+            .add_edge(NodeKey::Start, "count")
+            .add_edge("count", PRINT_STATE)
+            .add_edge(PRINT_STATE, INCREASE_COUNTER)
+            .add_edge(INCREASE_COUNTER, "confirm")
+            .add_edge("confirm", NodeKey::End);
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        graph
+            .set_mode(mode)
+            .add_node(ADD_LOG, subgraph.compile()?)
+            .add_edge(NodeKey::Start, ADD_LOG)
+            .add_edge(ADD_LOG, NodeKey::End);
+        let graph = graph.compile()?;
+        let response = graph
+            .clone()
+            .run(context.new_request(Default::default()))
+            .await?;
+        // the parent pauses at the subgraph node instead of going on
+        let interrupted = response.interrupted.expect("run is interrupted");
+        assert_eq!(interrupted.node, ADD_LOG);
+        assert_eq!(interrupted.kind, InterruptKind::Node);
+        assert_eq!(interrupted.payload, Some(serde_json::json!("approve?")));
+        assert_eq!(
+            interrupted.checkpoint.state["visited"],
+            serde_json::json!(true)
+        );
+        let request = || {
+            context
+                .new_request(Default::default())
+                .with_resume(serde_json::json!("yes"))
+        };
+        // the subgraph goes on from its approval, the later confirmation still pauses
+        let response = graph
+            .clone()
+            .run_from_checkpoint(request(), interrupted.checkpoint)
+            .await?;
+        let interrupted = response.interrupted.expect("run is interrupted again");
+        assert_eq!(interrupted.payload, Some(serde_json::json!("confirm?")));
+        assert_eq!(
+            interrupted.checkpoint.state["approved"],
+            serde_json::json!("yes")
+        );
+        let response = graph
+            .clone()
+            .run_from_checkpoint(request(), interrupted.checkpoint)
+            .await?;
+        assert!(response.interrupted.is_none());
+        assert_eq!(response.state["visited"], serde_json::json!(true));
+        assert_eq!(response.state["approved"], serde_json::json!("yes"));
+        assert_eq!(response.state["confirmed"], serde_json::json!("yes"));
+        // the nodes before the interrupt ran only once
+        assert_eq!(context.state.countor.load(Ordering::SeqCst), 1);
+    }
+    Ok(())
+}
+
+async fn ask_confirmation(state: State, Resume(value): Resume) -> Result<(), crabgraph::NodeError> {
+    let Some(value) = value else {
+        return Err(Interrupt::new(serde_json::json!("confirm?")).into());
+    };
+    state.apply_modification(Insert("confirmed", value)).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fan_out_join_all() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_reducer("sum", Sum)
+        .add_node(ADD_LOG, mark_visited)
+        .add_node(INCREASE_COUNTER, add_input_after_nap)
+        .add_node(PRINT_STATE, count_calls)
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(
+            ADD_LOG,
+            FanOut::new([INCREASE_COUNTER], || {
+                (1..=3)
+                    .map(|n| Dispatch::new(INCREASE_COUNTER, serde_json::json!(n)))
+                    .collect::<Vec<_>>()
+            }),
+        )
+        .add_edge(INCREASE_COUNTER, PRINT_STATE)
+        .add_edge(PRINT_STATE, NodeKey::End)
+        .set_join(PRINT_STATE, Join::All);
+    let graph = graph.compile()?;
+    let response = graph.run(context.new_request(Default::default())).await?;
+    // the reduce waits for every branch of the fan out
+    assert_eq!(context.state.countor.load(Ordering::SeqCst), 1);
+    assert_eq!(response.executions.last().unwrap().node, PRINT_STATE);
+    assert_eq!(response.state["sum"], serde_json::json!(6));
+    Ok(())
+}
+
+async fn add_input_after_nap(Input(n): Input<u64>) -> Insert {
+    tokio::time::sleep(std::time::Duration::from_millis(10 * n)).await;
+    Insert("sum", serde_json::json!(n))
+}
+
+#[tokio::test]
+async fn test_tuple_output() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node(ADD_LOG, greet_in_parts)
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, NodeKey::End);
+    let graph = graph.compile()?;
+    let response = graph.run(context.new_request(Default::default())).await?;
+    // the elements are applied in order, the later write wins
+    assert_eq!(response.state["greeting"], serde_json::json!("hi"));
+    assert_eq!(response.state["name"], serde_json::json!("crab"));
+    Ok(())
+}
+
+async fn greet_in_parts() -> (
+    Option<Insert>,
+    Option<Insert>,
+    Result<Insert, crabgraph::NodeError>,
+) {
+    (
+        Some(Insert("greeting", serde_json::json!("hello"))),
+        Some(Insert("name", serde_json::json!("crab"))),
+        Ok(Insert("greeting", serde_json::json!("hi"))),
+    )
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, schemars::JsonSchema, GraphState)]
+#[serde(rename_all = "camelCase")]
+struct Survey {
+    #[serde(default)]
+    #[reducer(Append)]
+    answer_log: Vec<String>,
+    #[serde(default, rename = "total")]
+    #[reducer(Sum)]
+    answer_count: u32,
+    #[serde(skip)]
+    #[allow(dead_code)]
+    draft: String,
+}
+
+#[tokio::test]
+async fn test_derive_graph_state_rename() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .set_mode(ExecutionMode::Superstep)
+        .set_state::<Survey>()
+        .add_node(ADD_LOG, || async {
+            SurveyUpdate::default()
+                .answer_log(vec!["yes".to_string()])
+                .answer_count(1)
+        })
+        .add_node(PRINT_STATE, || async {
+            SurveyUpdate::default()
+                .answer_log(vec!["no".to_string()])
+                .answer_count(1)
+        })
+        .add_edge(NodeKey::Start, [ADD_LOG, PRINT_STATE])
+        .add_edge(ADD_LOG, NodeKey::End)
+        .add_edge(PRINT_STATE, NodeKey::End);
+    let graph = graph.compile()?;
+    let response = graph
+        .run_typed::<Survey>(context.new_request(Default::default()))
+        .await?;
+    // the reducers and the writes use the keys Deserialize reads
+    assert_eq!(response.output.answer_log, ["yes", "no"]);
+    assert_eq!(response.output.answer_count, 2);
+    assert_eq!(
+        response.response.state["answerLog"],
+        serde_json::json!(["yes", "no"])
+    );
+    assert_eq!(response.response.state["total"], serde_json::json!(2));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_state_ops_in_nodes() -> anyhow::Result<()> {
+    for mode in [ExecutionMode::Async, ExecutionMode::Superstep] {
+        let mut graph = crate::Graph::<App>::new();
+        let context = Context::<App>::default();
+        graph
+            .set_mode(mode)
+            .add_node(ADD_LOG, push_log)
+            .add_node(INCREASE_COUNTER, increment_index)
+            .add_edge(NodeKey::Start, ADD_LOG)
+            .add_edge(ADD_LOG, INCREASE_COUNTER)
+            .add_edge(INCREASE_COUNTER, NodeKey::End);
+        let graph = graph.compile()?;
+        let response = graph.run(context.new_request(Default::default())).await?;
+        assert_eq!(response.state["__log"], serde_json::json!(["hello world"]));
+        assert_eq!(response.state["stats"], serde_json::json!({ "index": 1 }));
+    }
+    Ok(())
+}
+
+async fn increment_index(state: State) -> Result<(), crabgraph::NodeError> {
+    state
+        .apply_modification(Increment::new("/stats/index", 1))
+        .await;
+    Ok(())
+}
+
+async fn push_log() -> Push {
+    Push::new("/__log", "hello world")
+}
+
+#[tokio::test]
+async fn test_sequence_extractors() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    let set_query: Arc<dyn Node<App>> = set_query.into_node();
+    graph
+        .add_node(ADD_LOG, set_query.then(copy_query))
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, NodeKey::End);
+    let graph = graph.compile()?;
+    let response = graph.run(context.new_request(Default::default())).await?;
+    // the second node extracts what the first one wrote
+    assert_eq!(response.state["copied"], serde_json::json!("crabs"));
+    Ok(())
+}
+
+async fn set_query() -> Set {
+    Set::new("/query", "crabs")
+}
+
+async fn copy_query(query: Field<Query, String>) -> Set {
+    Set::new("/copied", query.value)
+}
+
+#[tokio::test]
+async fn test_stream_started_after_permits() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .set_max_concurrency(1)
+        .add_node(INCREASE_COUNTER, run_alone)
+        .add_node(ADD_LOG, run_alone)
+        .add_edge(NodeKey::Start, [INCREASE_COUNTER, ADD_LOG])
+        .add_edge(INCREASE_COUNTER, NodeKey::End)
+        .add_edge(ADD_LOG, NodeKey::End);
+    let graph = graph.compile()?;
+    let events = graph
+        .stream(context.new_request(Default::default()), StreamMode::Updates)
+        .collect::<Vec<_>>()
+        .await;
+    // the node waiting for the permit is reported once the other one finished
+    let started_or_finished: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            RunEvent::NodeStarted { .. } => Some("started"),
+            RunEvent::NodeFinished { .. } => Some("finished"),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        started_or_finished,
+        ["started", "finished", "started", "finished"]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_dropped() -> anyhow::Result<()> {
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_node(ADD_LOG, count_after_nap)
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, NodeKey::End);
+    let graph = graph.compile()?;
+    let mut events = graph.stream(context.new_request(Default::default()), StreamMode::Updates);
+    events.next().await;
+    drop(events);
+    tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+    // nobody listens, the run stopped with the stream
+    assert_eq!(context.state.countor.load(Ordering::SeqCst), 0);
+    Ok(())
+}
+
+async fn count_after_nap(context: Context<App>) -> Result<(), crabgraph::NodeError> {
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    context.state.countor.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subgraph_reducers() -> anyhow::Result<()> {
+    let mut subgraph = crate::Graph::<App>::new();
+    subgraph
+        .add_node(INCREASE_COUNTER, push_log_b)
+        .add_edge(NodeKey::Start, INCREASE_COUNTER)
+        .add_edge(INCREASE_COUNTER, NodeKey::End);
+    let mut graph = crate::Graph::<App>::new();
+    let context = Context::<App>::default();
+    graph
+        .add_reducer("log", Append)
+        .add_node(ADD_LOG, || async {
+            Insert("log", serde_json::json!(["a"]))
+        })
+        .add_node(PRINT_STATE, subgraph.compile()?)
+        .add_edge(NodeKey::Start, ADD_LOG)
+        .add_edge(ADD_LOG, PRINT_STATE)
+        .add_edge(PRINT_STATE, NodeKey::End);
+    let graph = graph.compile()?;
+    let response = graph.run(context.new_request(Default::default())).await?;
+    // the subgraph appends through the reducer of the parent, nothing is appended twice
+    assert_eq!(response.state["log"], serde_json::json!(["a", "b"]));
+    Ok(())
+}
+
+async fn push_log_b() -> Push {
+    Push::new("/log", "b")
+}